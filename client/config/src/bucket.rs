@@ -0,0 +1,71 @@
+use parking_lot::Mutex;
+use std::time::Instant;
+
+/// State of a token bucket: the tokens left and when it was last refilled
+#[derive(Clone, Copy)]
+pub struct Tokens {
+	left: f64,
+	refilled: Instant,
+}
+
+impl Tokens {
+	/// A bucket holding `burst` tokens
+	pub fn full(burst: f64) -> Self {
+		Tokens {
+			left: burst,
+			refilled: Instant::now(),
+		}
+	}
+
+	/// Refill at `rate` tokens per second up to `burst`, then take a token if there is one left
+	pub fn take(&mut self, rate: f64, burst: f64) -> bool {
+		self.take_many(rate, burst, 1)
+	}
+
+	/// Like `take`, taking `count` tokens if there are as many left, or none at all
+	pub fn take_many(&mut self, rate: f64, burst: f64, count: u32) -> bool {
+		let now = Instant::now();
+		let elapsed = now.duration_since(self.refilled).as_secs_f64();
+		self.left = (self.left + elapsed * rate).min(burst);
+		self.refilled = now;
+		let count = f64::from(count);
+		if self.left >= count {
+			self.left -= count;
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/// Allows `rate` events per second on average and up to `burst` at once
+pub struct Bucket {
+	rate: f64,
+	burst: f64,
+	tokens: Mutex<Tokens>,
+}
+
+impl Bucket {
+	/// `None` if `rate` isn't positive, meaning unlimited
+	pub fn new(rate: f64, burst: u32) -> Option<Self> {
+		if rate <= 0.0 {
+			return None;
+		}
+		let burst = f64::from(burst.max(1));
+		Some(Bucket {
+			rate,
+			burst,
+			tokens: Mutex::new(Tokens::full(burst)),
+		})
+	}
+
+	/// Take a token, if there is one left
+	pub fn take(&self) -> bool {
+		self.take_many(1)
+	}
+
+	/// Take `count` tokens, if there are as many left
+	pub fn take_many(&self, count: u32) -> bool {
+		self.tokens.lock().take_many(self.rate, self.burst, count)
+	}
+}
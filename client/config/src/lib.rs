@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+mod bucket;
+
+pub use bucket::{Bucket, Tokens};
+
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_RESOLVED_MAX_AGE: u64 = 60;
+const DEFAULT_RETRY_AFTER: u64 = 10;
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+const DEFAULT_COMPRESSION_CACHE_ENTRIES: usize = 256;
+const DEFAULT_CORS_MAX_AGE: u64 = 24 * 60 * 60;
+const DEFAULT_ACCESS_LOG_TARGET: &str = "lfs-http";
+const DEFAULT_PER_IP_RATE: f64 = 20.0;
+const DEFAULT_PER_IP_BURST: u32 = 50;
+const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+const DEFAULT_MAX_CONNECTIONS_PER_IP: usize = 32;
+const DEFAULT_IDLE_TIMEOUT: u64 = 30;
+const DEFAULT_HEADER_TIMEOUT: u64 = 10;
+const DEFAULT_REQUEST_TIMEOUT: u64 = 60;
+
+/// Which state of the chain paths are resolved against
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolveMode {
+	/// The best block, which may still be reverted
+	Best,
+	/// The last finalized block
+	Finalized,
+}
+
+/// Limits protecting the server from being overwhelmed, `0` disables a limit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LimitsConfig {
+	/// Requests per second a single address may make on average
+	pub per_ip_rate: f64,
+	/// Requests a single address may make at once, beyond the average rate
+	pub per_ip_burst: u32,
+	/// Requests per second the server accepts in total on average
+	pub global_rate: f64,
+	/// Requests the server accepts at once, beyond the average rate
+	pub global_burst: u32,
+	/// Connections open at the same time
+	pub max_connections: usize,
+	/// Connections open at the same time from a single address
+	pub max_connections_per_ip: usize,
+	/// Seconds a connection may neither send nor receive anything before it is closed
+	pub idle_timeout: u64,
+	/// Seconds receiving the headers of a request may take, counted from accepting the
+	/// connection or the first byte of a request following another one
+	pub header_timeout: u64,
+	/// Seconds handling a request, including receiving its body, may take
+	pub request_timeout: u64,
+}
+
+/// How lines of the access log are written
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+	/// Space separated fields
+	Text,
+	/// A JSON object per line
+	Json,
+}
+
+/// Configuration of the access log
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AccessLogConfig {
+	/// Log every request at the `info` level
+	pub enabled: bool,
+	/// The `log` target to write to
+	pub target: String,
+	/// How lines are written
+	pub format: AccessLogFormat,
+}
+
+/// A resolver mounted under a path prefix
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RouteConfig {
+	/// The path prefix, e.g. `/u/`, which is removed before resolving
+	pub prefix: String,
+	/// The name of the resolver, e.g. `raw` or `user-data`
+	pub resolver: String,
+}
+
+/// Configuration of the HTTP uploads
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct UploadConfig {
+	/// Accept uploads via `POST /lfs` and `PUT /lfs`
+	pub enabled: bool,
+	/// Maximum size of a single upload in bytes
+	pub max_size: u64,
+	/// Require uploads to be signed by one of the `allowed_accounts`
+	pub require_signature: bool,
+	/// SS58 encoded sr25519 accounts allowed to upload
+	pub allowed_accounts: Vec<String>,
+}
+
+/// Configuration of the response compression
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+	/// Compress responses if the client accepts it
+	pub enabled: bool,
+	/// Only compress responses of at least that many bytes
+	pub min_size: usize,
+	/// How many compressed responses to keep in memory
+	pub cache_entries: usize,
+}
+
+/// Cross-Origin Resource Sharing configuration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CorsConfig {
+	/// Origins allowed to access resources, `*` for any. Empty disables CORS.
+	pub allowed_origins: Vec<String>,
+	/// Methods allowed in cross-origin requests
+	pub allowed_methods: Vec<String>,
+	/// Request headers allowed in cross-origin requests
+	pub allowed_headers: Vec<String>,
+	/// Seconds the result of a preflight request may be cached
+	pub max_age: u64,
+}
+
+/// Configuration for the HTTP server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+	/// The address to listen on
+	pub listen: SocketAddr,
+	/// Seconds clients may cache responses of resolved, non-content-addressed, paths
+	pub resolved_max_age: u64,
+	/// Render a listing of entries for directories without an index page
+	pub directory_listing: bool,
+	/// Seconds clients should wait before retrying, if data isn't available yet
+	pub retry_after: u64,
+	/// The block to resolve paths at, unless the request pins one
+	pub resolve_mode: ResolveMode,
+	/// Serve the root account's entries for hosts not registered on chain
+	pub unknown_host_fallback: bool,
+	/// The resolvers to ask, in order, for paths under their prefix
+	pub routes: Vec<RouteConfig>,
+	/// Upload configuration
+	pub upload: UploadConfig,
+	/// Compression configuration
+	pub compression: CompressionConfig,
+	/// CORS configuration
+	pub cors: CorsConfig,
+	/// Access log configuration
+	pub access_log: AccessLogConfig,
+	/// Rate limits, connection caps and timeouts
+	pub limits: LimitsConfig,
+	/// Additional headers to send with every response, e.g. `Content-Security-Policy`
+	pub headers: BTreeMap<String, String>,
+}
+
+impl core::default::Default for ResolveMode {
+	fn default() -> ResolveMode {
+		ResolveMode::Best
+	}
+}
+
+impl core::default::Default for LimitsConfig {
+	fn default() -> LimitsConfig {
+		LimitsConfig {
+			per_ip_rate: DEFAULT_PER_IP_RATE,
+			per_ip_burst: DEFAULT_PER_IP_BURST,
+			global_rate: 0.0,
+			global_burst: 0,
+			max_connections: DEFAULT_MAX_CONNECTIONS,
+			max_connections_per_ip: DEFAULT_MAX_CONNECTIONS_PER_IP,
+			idle_timeout: DEFAULT_IDLE_TIMEOUT,
+			header_timeout: DEFAULT_HEADER_TIMEOUT,
+			request_timeout: DEFAULT_REQUEST_TIMEOUT,
+		}
+	}
+}
+
+impl core::default::Default for AccessLogConfig {
+	fn default() -> AccessLogConfig {
+		AccessLogConfig {
+			enabled: true,
+			target: DEFAULT_ACCESS_LOG_TARGET.to_owned(),
+			format: AccessLogFormat::Text,
+		}
+	}
+}
+
+impl core::default::Default for UploadConfig {
+	fn default() -> UploadConfig {
+		UploadConfig {
+			enabled: false,
+			max_size: DEFAULT_MAX_UPLOAD_SIZE,
+			require_signature: true,
+			allowed_accounts: Default::default(),
+		}
+	}
+}
+
+impl core::default::Default for CompressionConfig {
+	fn default() -> CompressionConfig {
+		CompressionConfig {
+			enabled: true,
+			min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+			cache_entries: DEFAULT_COMPRESSION_CACHE_ENTRIES,
+		}
+	}
+}
+
+impl core::default::Default for CorsConfig {
+	fn default() -> CorsConfig {
+		CorsConfig {
+			allowed_origins: Default::default(),
+			allowed_methods: vec!["GET".into(), "HEAD".into(), "OPTIONS".into()],
+			allowed_headers: vec![
+				"Content-Type".into(),
+				"X-LFS-Account".into(),
+				"X-LFS-Signature".into(),
+			],
+			max_age: DEFAULT_CORS_MAX_AGE,
+		}
+	}
+}
+
+impl core::default::Default for ServerConfig {
+	fn default() -> ServerConfig {
+		ServerConfig {
+			listen: ([127, 0, 0, 1], 8080).into(),
+			resolved_max_age: DEFAULT_RESOLVED_MAX_AGE,
+			directory_listing: false,
+			retry_after: DEFAULT_RETRY_AFTER,
+			resolve_mode: ResolveMode::Best,
+			unknown_host_fallback: true,
+			routes: vec![
+				RouteConfig {
+					prefix: "/".into(),
+					resolver: "raw".into(),
+				},
+				RouteConfig {
+					prefix: "/".into(),
+					resolver: "user-data".into(),
+				},
+			],
+			upload: Default::default(),
+			compression: Default::default(),
+			cors: Default::default(),
+			access_log: Default::default(),
+			limits: Default::default(),
+			headers: Default::default(),
+		}
+	}
+}
@@ -0,0 +1,79 @@
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Response};
+use log::{log, Level};
+use std::time::Instant;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+
+/// What a path was resolved to, attached to the response for the access log
+#[derive(Clone, Debug)]
+pub struct AccessInfo {
+	/// The base64 encoded `LfsId` served
+	pub key: String,
+	/// The resolver step that yielded the key, if known
+	pub step: Option<&'static str>,
+}
+
+/// Writes a line per request to the configured `log` target
+pub struct AccessLog {
+	enabled: bool,
+	target: String,
+	format: AccessLogFormat,
+}
+
+impl AccessLog {
+	pub fn new(config: &AccessLogConfig) -> Self {
+		AccessLog {
+			enabled: config.enabled,
+			target: config.target.clone(),
+			format: config.format,
+		}
+	}
+
+	/// The `log` target to write to
+	pub fn target(&self) -> &str {
+		&self.target
+	}
+
+	/// Log the `response` to the request of `method` for `path`, started at `started`
+	pub fn log(&self, method: &Method, path: &str, response: &Response<Body>, started: Instant) {
+		if !self.enabled || !log::log_enabled!(target: &self.target, Level::Info) {
+			return;
+		}
+		let info = response.extensions().get::<AccessInfo>();
+		let key = info.map(|i| i.key.as_str());
+		let step = info.and_then(|i| i.step);
+		let status = response.status().as_u16();
+		let bytes = response.body().size_hint().exact();
+		let duration = started.elapsed().as_micros() as f64 / 1000.0;
+
+		match self.format {
+			AccessLogFormat::Text => log!(
+				target: &self.target,
+				Level::Info,
+				"{} {} {} {} {:.3}ms key={} step={}",
+				method,
+				path,
+				status,
+				bytes.map(|b| b.to_string()).unwrap_or_else(|| "-".to_owned()),
+				duration,
+				key.unwrap_or("-"),
+				step.unwrap_or("-"),
+			),
+			AccessLogFormat::Json => log!(
+				target: &self.target,
+				Level::Info,
+				"{}",
+				serde_json::json!({
+					"method": method.as_str(),
+					"path": path,
+					"status": status,
+					"bytes": bytes,
+					"duration_ms": duration,
+					"key": key,
+					"step": step,
+				})
+			),
+		}
+	}
+}
@@ -0,0 +1,136 @@
+use flate2::{write::GzEncoder, Compression};
+use hyper::{body::Bytes, header, Body, Request};
+use lru::LruCache;
+use parking_lot::Mutex;
+use sp_lfs_core::LfsId;
+use std::io::Write;
+
+use crate::config::CompressionConfig;
+
+/// The content encodings we can compress with
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Encoding {
+	Brotli,
+	Zstd,
+	Gzip,
+}
+
+/// Ordered by our preference
+const SUPPORTED: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+impl Encoding {
+	/// The token used in `Accept-Encoding` and `Content-Encoding`
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Encoding::Brotli => "br",
+			Encoding::Zstd => "zstd",
+			Encoding::Gzip => "gzip",
+		}
+	}
+
+	fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+		match self {
+			Encoding::Brotli => {
+				let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+				writer.write_all(data)?;
+				Ok(writer.into_inner())
+			}
+			Encoding::Zstd => zstd::stream::encode_all(data, 0),
+			Encoding::Gzip => {
+				let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+				encoder.write_all(data)?;
+				encoder.finish()
+			}
+		}
+	}
+}
+
+/// Whether it is worth compressing data of the given mime type
+fn is_compressible(mime: &str) -> bool {
+	mime.starts_with("text/")
+		|| match mime {
+			"application/javascript"
+			| "application/json"
+			| "application/xml"
+			| "image/svg+xml"
+			| "font/ttf"
+			| "application/vnd.ms-fontobject" => true,
+			_ => false,
+		}
+}
+
+/// Pick the encoding from the clients `Accept-Encoding` we prefer most
+fn negotiate(req: &Request<Body>) -> Option<Encoding> {
+	let accepted = req
+		.headers()
+		.get_all(header::ACCEPT_ENCODING)
+		.iter()
+		.filter_map(|h| h.to_str().ok())
+		.flat_map(|h| h.split(','))
+		.filter_map(|entry| {
+			let mut parts = entry.split(';').map(|p| p.trim());
+			let token = parts.next()?;
+			let disabled = parts.any(|p| {
+				p.starts_with("q=") && p[2..].parse::<f32>().map(|q| q <= 0.0).unwrap_or(false)
+			});
+			if disabled {
+				None
+			} else {
+				Some(token.to_ascii_lowercase())
+			}
+		})
+		.collect::<Vec<_>>();
+
+	SUPPORTED
+		.iter()
+		.find(|e| accepted.iter().any(|a| a == e.as_str()))
+		.cloned()
+}
+
+/// Compresses responses and memoizes the compressed variants
+pub struct Compressor<L: LfsId> {
+	enabled: bool,
+	min_size: usize,
+	cache: Mutex<LruCache<(L, Encoding), Bytes>>,
+}
+
+impl<L: LfsId> Compressor<L> {
+	pub fn new(config: &CompressionConfig) -> Self {
+		Compressor {
+			enabled: config.enabled,
+			min_size: config.min_size,
+			cache: Mutex::new(LruCache::new(config.cache_entries.max(1))),
+		}
+	}
+
+	/// Compress `data` stored under `key` with an encoding the client accepts, if
+	/// the `mime` type is worth compressing. Returns the encoding and compressed data.
+	pub fn compress(
+		&self,
+		req: &Request<Body>,
+		key: &L,
+		mime: Option<&str>,
+		data: &[u8],
+	) -> Option<(Encoding, Bytes)> {
+		if !self.enabled
+			|| data.len() < self.min_size
+			|| !mime.map(is_compressible).unwrap_or(false)
+		{
+			return None;
+		}
+		let encoding = negotiate(req)?;
+		let cache_key = (key.clone(), encoding);
+
+		if let Some(compressed) = self.cache.lock().get(&cache_key) {
+			return Some((encoding, compressed.clone()));
+		}
+
+		let compressed = Bytes::from(encoding.compress(data).ok()?);
+		if compressed.len() >= data.len() {
+			// not worth it
+			return None;
+		}
+		self.cache.lock().put(cache_key, compressed.clone());
+		Some((encoding, compressed))
+	}
+}
@@ -0,0 +1,132 @@
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use std::convert::TryFrom;
+
+use crate::config::{CorsConfig, ServerConfig};
+
+/// The CORS policy as configured
+struct Cors {
+	/// `None` allows any origin
+	allowed_origins: Option<Vec<HeaderValue>>,
+	allowed_methods: HeaderValue,
+	allowed_headers: HeaderValue,
+	max_age: HeaderValue,
+}
+
+impl Cors {
+	fn new(config: &CorsConfig) -> Result<Option<Self>, String> {
+		if config.allowed_origins.is_empty() {
+			return Ok(None);
+		}
+		let allowed_origins = if config.allowed_origins.iter().any(|o| o == "*") {
+			None
+		} else {
+			Some(
+				config
+					.allowed_origins
+					.iter()
+					.map(|o| parse_value(o))
+					.collect::<Result<Vec<_>, _>>()?,
+			)
+		};
+		Ok(Some(Cors {
+			allowed_origins,
+			allowed_methods: parse_value(&config.allowed_methods.join(", "))?,
+			allowed_headers: parse_value(&config.allowed_headers.join(", "))?,
+			max_age: HeaderValue::from(config.max_age),
+		}))
+	}
+
+	/// The value for `Access-Control-Allow-Origin`, if `origin` is allowed
+	fn allow_origin(&self, origin: Option<&HeaderValue>) -> Option<HeaderValue> {
+		match self.allowed_origins {
+			None => Some(HeaderValue::from_static("*")),
+			Some(ref allowed) => origin.filter(|o| allowed.contains(o)).cloned(),
+		}
+	}
+}
+
+fn parse_value(value: &str) -> Result<HeaderValue, String> {
+	HeaderValue::from_str(value).map_err(|e| format!("Invalid header value {}: {}", value, e))
+}
+
+/// Headers added to every response: CORS and custom ones
+pub struct HeaderRules {
+	cors: Option<Cors>,
+	custom: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HeaderRules {
+	pub fn new(config: &ServerConfig) -> Result<Self, String> {
+		let custom = config
+			.headers
+			.iter()
+			.map(|(name, value)| {
+				HeaderName::try_from(name.as_str())
+					.map_err(|e| format!("Invalid header name {}: {}", name, e))
+					.and_then(|n| parse_value(value).map(|v| (n, v)))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(HeaderRules {
+			cors: Cors::new(&config.cors)?,
+			custom,
+		})
+	}
+
+	/// Answer an `OPTIONS` request, including CORS preflight requests
+	pub fn preflight(&self, req: &Request<Body>) -> Response<Body> {
+		let mut response = Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.body(Body::empty())
+			.expect("Empty doesn't fail");
+
+		let headers = response.headers_mut();
+		match self.cors {
+			Some(ref cors)
+				if req
+					.headers()
+					.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD) =>
+			{
+				if let Some(origin) = cors.allow_origin(req.headers().get(header::ORIGIN)) {
+					headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+					headers.insert(
+						header::ACCESS_CONTROL_ALLOW_METHODS,
+						cors.allowed_methods.clone(),
+					);
+					headers.insert(
+						header::ACCESS_CONTROL_ALLOW_HEADERS,
+						cors.allowed_headers.clone(),
+					);
+					headers.insert(header::ACCESS_CONTROL_MAX_AGE, cors.max_age.clone());
+				}
+				headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+			}
+			_ => {
+				headers.insert(
+					header::ALLOW,
+					HeaderValue::from_static("GET, HEAD, OPTIONS, POST, PUT"),
+				);
+			}
+		}
+		response
+	}
+
+	/// Add the configured headers to the `response` of a request from `origin`
+	pub fn apply(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+		let headers = response.headers_mut();
+		if let Some(ref cors) = self.cors {
+			if let Some(origin) = cors.allow_origin(origin) {
+				headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+				headers.insert(
+					header::ACCESS_CONTROL_EXPOSE_HEADERS,
+					HeaderValue::from_static("ETag, Last-Modified, Location"),
+				);
+			}
+			headers.append(header::VARY, HeaderValue::from_static("Origin"));
+		}
+		for (name, value) in self.custom.iter() {
+			headers.insert(name.clone(), value.clone());
+		}
+	}
+}
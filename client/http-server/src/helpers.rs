@@ -1,5 +1,7 @@
 use base64;
 use codec::{Decode, Encode};
+use hyper::Uri;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// helper to decode a base64 encoded string to Lfs
 pub fn b64decode<'a, D: Decode>(input: &'a [u8]) -> Option<D> {
@@ -12,3 +14,80 @@ pub fn b64decode<'a, D: Decode>(input: &'a [u8]) -> Option<D> {
 pub fn b64encode<'a, E: Encode>(input: E) -> String {
 	base64::encode_config(&input.encode(), base64::URL_SAFE)
 }
+
+/// helper to get the full seconds since the epoch, the precision of HTTP dates
+pub fn seconds(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+/// helper to find the value of the query parameter `name`, empty if given without one
+pub fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+	uri.query()?.split('&').find_map(|p| {
+		let mut parts = p.splitn(2, '=');
+		if parts.next() == Some(name) {
+			Some(parts.next().unwrap_or(""))
+		} else {
+			None
+		}
+	})
+}
+
+/// helper to guess the mime type from the extension of the path
+pub fn mime_for_path(path: &str) -> Option<&'static str> {
+	if path.ends_with('/') {
+		// directories are served by their index page
+		return Some("text/html; charset=utf-8");
+	}
+	let file_name = path.rsplit('/').next()?;
+	let (_, ext) = file_name.split_at(file_name.rfind('.')? + 1);
+	Some(match ext.to_ascii_lowercase().as_str() {
+		"html" | "htm" => "text/html; charset=utf-8",
+		"css" => "text/css; charset=utf-8",
+		"js" => "application/javascript",
+		"json" => "application/json",
+		"xml" => "application/xml",
+		"txt" => "text/plain; charset=utf-8",
+		"md" => "text/markdown; charset=utf-8",
+		"adoc" => "text/asciidoc; charset=utf-8",
+		"rtf" => "text/rtf",
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"svg" => "image/svg+xml",
+		"eot" => "application/vnd.ms-fontobject",
+		"ttf" => "font/ttf",
+		"woff" => "font/woff",
+		"woff2" => "font/woff2",
+		_ => return None,
+	})
+}
+
+/// helper to escape text for use in HTML
+fn html_escape(input: &str) -> String {
+	input
+		.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&#39;")
+}
+
+/// helper to render the HTML listing of the `entries` of the directory at `path`
+pub fn render_listing(path: &str, entries: &[String]) -> String {
+	let path = html_escape(path);
+	let items = entries
+		.iter()
+		.map(|e| {
+			let e = html_escape(e);
+			format!("<li><a href=\"{}\">{}</a></li>", e, e)
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+	format!(
+		"<!DOCTYPE html>\n<html><head><title>Index of {0}</title></head>\n\
+		 <body><h1>Index of {0}</h1><ul>\n{1}\n</ul></body></html>",
+		path, items
+	)
+}
@@ -1,17 +1,65 @@
-use futures::future;
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Future, FutureExt, TryFutureExt};
+use httpdate::{fmt_http_date, parse_http_date};
+use hyper::header::{HeaderName, HeaderValue};
 use hyper::service::Service;
-use hyper::{header, http, Body, Request, Response, Server, StatusCode};
+use hyper::{header, http, Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, error, info, warn};
+use parking_lot::{Mutex, RwLock};
 use sp_lfs_cache::Cache;
-use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 
+mod access_log;
+mod compression;
+mod headers;
 mod helpers;
+pub mod limits;
+pub mod router;
+pub mod rules;
 mod traits;
+pub mod upload;
 #[cfg(feature = "user-data")]
 pub mod user_data;
 
+use access_log::{AccessInfo, AccessLog};
+use compression::Compressor;
+pub use config::ServerConfig;
+use headers::HeaderRules;
 pub use helpers::{b64decode, b64encode};
-pub use traits::Resolver;
+use helpers::{mime_for_path, render_listing, seconds};
+use limits::{Connection, HeaderTimer, LimitedIncoming, Limiter};
+pub use router::{Mount, Router};
+pub use sc_lfs_config as config;
+pub use traits::{Resolution, Resolved, ResolvedKind, Resolver};
+use upload::Uploader;
+
+/// Whether the client already holds the current version, according to the
+/// conditional headers of the request
+fn is_not_modified<L: sp_lfs_core::LfsId>(
+	req: &Request<Body>,
+	key: &L,
+	last_modified: Option<SystemTime>,
+) -> bool {
+	let headers = req.headers();
+	if let Some(etag) = headers.get(header::IF_NONE_MATCH) {
+		// `If-None-Match` takes precedence over `If-Modified-Since`
+		return Some(key) == b64decode::<L>(etag.as_bytes()).as_ref();
+	}
+	match (
+		last_modified,
+		headers
+			.get(header::IF_MODIFIED_SINCE)
+			.and_then(|h| h.to_str().ok())
+			.and_then(|h| parse_http_date(h).ok()),
+	) {
+		(Some(modified), Some(since)) => seconds(modified) <= seconds(since),
+		_ => false,
+	}
+}
 
 fn not_found() -> Response<Body> {
 	Response::builder()
@@ -20,83 +68,314 @@ fn not_found() -> Response<Body> {
 		.expect("Building this simple response doesn't fail. qed")
 }
 
-struct LfsServer<C, R, L> {
+fn too_many_requests() -> Response<Body> {
+	Response::builder()
+		.status(StatusCode::TOO_MANY_REQUESTS)
+		.header(header::RETRY_AFTER, 1)
+		.body(Body::from("429 - Too many requests"))
+		.expect("Building this simple response doesn't fail. qed")
+}
+
+fn request_timeout() -> Response<Body> {
+	Response::builder()
+		.status(StatusCode::REQUEST_TIMEOUT)
+		.header(header::CONNECTION, "close")
+		.body(Body::from("408 - Request timeout"))
+		.expect("Building this simple response doesn't fail. qed")
+}
+
+/// `Cache-Control` for content-addressed responses, which never change
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Marks responses of a pending, not yet confirmed, version
+pub const PREVIEW_HEADER: &str = "x-lfs-preview";
+
+struct LfsServer<C, R, L: sp_lfs_core::LfsId> {
 	cache: C,
 	resolver: R,
-	_marker: PhantomData<L>,
+	config: Arc<ServerConfig>,
+	uploader: Arc<Uploader>,
+	compressor: Arc<Compressor<L>>,
+	headers: Arc<HeaderRules>,
+	access_log: Arc<AccessLog>,
+	limiter: Arc<Limiter>,
+	/// The address of the peer of the connection served
+	remote: Option<IpAddr>,
+	/// Tracks the request headers received on the connection served
+	header_timer: Option<HeaderTimer>,
 }
 
-impl<C, R, LfsId> LfsServer<C, R, LfsId> {
-	fn new(cache: C, resolver: R) -> Self {
-		Self {
+impl<C, R, LfsId> LfsServer<C, R, LfsId>
+where
+	LfsId: sp_lfs_core::LfsId,
+{
+	fn new(cache: C, resolver: R, config: ServerConfig) -> Result<Self, String> {
+		Ok(Self {
 			cache,
 			resolver,
-			_marker: Default::default(),
+			uploader: Arc::new(Uploader::new(&config.upload)?),
+			compressor: Arc::new(Compressor::new(&config.compression)),
+			headers: Arc::new(HeaderRules::new(&config)?),
+			access_log: Arc::new(AccessLog::new(&config.access_log)),
+			limiter: Arc::new(Limiter::new(&config.limits)),
+			remote: None,
+			header_timer: None,
+			config: Arc::new(config),
+		})
+	}
+}
+
+impl<C, R, LfsId> Clone for LfsServer<C, R, LfsId>
+where
+	C: Clone,
+	R: Clone,
+	LfsId: sp_lfs_core::LfsId,
+{
+	fn clone(&self) -> Self {
+		Self {
+			cache: self.cache.clone(),
+			resolver: self.resolver.clone(),
+			config: self.config.clone(),
+			uploader: self.uploader.clone(),
+			compressor: self.compressor.clone(),
+			headers: self.headers.clone(),
+			access_log: self.access_log.clone(),
+			limiter: self.limiter.clone(),
+			remote: self.remote,
+			header_timer: self.header_timer.clone(),
 		}
 	}
 }
 
-impl<C, R, LfsId> Service<Request<Body>> for LfsServer<C, R, LfsId>
+impl<C, R, LfsId> LfsServer<C, R, LfsId>
 where
 	C: Cache<LfsId>,
 	R: Resolver<LfsId>,
 	LfsId: sp_lfs_core::LfsId,
+{
+	fn serve(&self, req: Request<Body>) -> Response<Body> {
+		let target = self.access_log.target();
+		let mut pending = None;
+		let mut not_found_page = None;
+		if let Some(it) = self.resolver.resolve(req.uri().clone(), req.headers()) {
+			for resolution in it {
+				debug!(target: target, "{} resolved to {:?}", req.uri(), resolution);
+				match resolution {
+					Resolution::Key(resolved) => {
+						if self.cache.exists(&resolved.key).unwrap_or(false) {
+							if resolved.kind == ResolvedKind::NotFound {
+								// a later resolution may still find the path itself
+								not_found_page = not_found_page.or(Some(resolved));
+								continue;
+							}
+							return self.respond_with(&req, resolved);
+						}
+						if resolved.expected {
+							// known, but not available locally (yet), don't fall back
+							pending = Some(resolved);
+							break;
+						}
+						debug!(target: target, "{:?} isn't available locally", resolved.key);
+					}
+					Resolution::Redirect(status, location) => {
+						return Response::builder()
+							.status(status)
+							.header(header::LOCATION, location)
+							.body(Body::empty())
+							.expect("Building this simple response doesn't fail. qed");
+					}
+					Resolution::Listing(entries) => {
+						if !self.config.directory_listing {
+							debug!(target: target, "Directory listing is disabled");
+						} else {
+							return Response::builder()
+								.status(StatusCode::OK)
+								.header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+								.body(Body::from(render_listing(req.uri().path(), &entries)))
+								.expect("Building this simple response doesn't fail. qed");
+						}
+					}
+				}
+			}
+		}
+		match pending {
+			Some(resolved) => Response::builder()
+				.status(StatusCode::SERVICE_UNAVAILABLE)
+				.header(header::RETRY_AFTER, self.config.retry_after)
+				.header(header::CACHE_CONTROL, "no-store")
+				.extension(AccessInfo {
+					key: b64encode(&resolved.key),
+					step: resolved.step,
+				})
+				.body(Body::from(format!(
+					"503 - {} is not available yet, please retry later",
+					b64encode(&resolved.key)
+				)))
+				.expect("Building this simple response doesn't fail. qed"),
+			None => match not_found_page {
+				Some(page) => self.respond_with(&req, page),
+				None => not_found(),
+			},
+		}
+	}
+
+	/// Respond with the data of the `resolved` key
+	fn respond_with(&self, req: &Request<Body>, resolved: Resolved<LfsId>) -> Response<Body> {
+		let key = resolved.key;
+		let cache_control = if resolved.preview {
+			"no-store".to_owned()
+		} else if resolved.immutable {
+			IMMUTABLE_CACHE_CONTROL.to_owned()
+		} else {
+			format!(
+				"public, max-age={}, must-revalidate",
+				self.config.resolved_max_age
+			)
+		};
+		let mut builder = Response::builder()
+			.extension(AccessInfo {
+				key: b64encode(&key),
+				step: resolved.step,
+			})
+			.header(header::ETAG, b64encode(&key))
+			.header(header::CACHE_CONTROL, cache_control)
+			.header(header::VARY, "Accept-Encoding");
+		if let Some(date) = resolved.last_modified {
+			builder = builder.header(header::LAST_MODIFIED, fmt_http_date(date));
+		}
+		if resolved.preview {
+			builder = builder.header(PREVIEW_HEADER, "1");
+		}
+		let mut content_type = None;
+		for (name, value) in resolved.headers.iter() {
+			// skip what isn't a valid header, rather than failing the response
+			match (
+				HeaderName::from_bytes(name.as_bytes()),
+				HeaderValue::from_str(value),
+			) {
+				(Ok(ref name), Ok(_)) if name == header::CONTENT_TYPE => {
+					content_type = Some(value.as_str())
+				}
+				(Ok(name), Ok(value)) => {
+					// replace what the server set, e.g. its `Cache-Control`, rather than adding to it
+					if let Some(headers) = builder.headers_mut() {
+						headers.insert(name, value);
+					}
+				}
+				_ => {}
+			}
+		}
+
+		let status = match resolved.kind {
+			ResolvedKind::Exact | ResolvedKind::Fallback => StatusCode::OK,
+			ResolvedKind::NotFound => StatusCode::NOT_FOUND,
+		};
+		if status == StatusCode::OK && is_not_modified(req, &key, resolved.last_modified) {
+			return builder
+				.status(StatusCode::NOT_MODIFIED)
+				.body(Body::empty())
+				.expect("Empty doesn't fail");
+		}
+		match self.cache.get(&key) {
+			Ok(data) => {
+				let mime = match resolved.kind {
+					_ if content_type.is_some() => content_type,
+					ResolvedKind::Exact => mime_for_path(req.uri().path()),
+					// fallbacks and not-found pages are served in place of whatever was requested
+					ResolvedKind::Fallback | ResolvedKind::NotFound => {
+						Some("text/html; charset=utf-8")
+					}
+				};
+				if let Some(mime) = mime {
+					builder = builder.header(header::CONTENT_TYPE, mime);
+				}
+				let body = match self.compressor.compress(req, &key, mime, &data) {
+					Some((encoding, compressed)) => {
+						builder = builder.header(header::CONTENT_ENCODING, encoding.as_str());
+						Body::from(compressed)
+					}
+					None => data.into(),
+				};
+				builder
+					.status(status)
+					.body(body)
+					.expect("Building this simple response doesn't fail. qed")
+			}
+			Err(_) => Response::builder()
+				.status(StatusCode::INTERNAL_SERVER_ERROR)
+				.body(Body::from(format!(
+					"Internal Server error key {:?} found, but couldn't be read.",
+					key
+				)))
+				.expect("Building this simple response doesn't fail. qed"),
+		}
+	}
+}
+
+impl<C, R, LfsId> Service<Request<Body>> for LfsServer<C, R, LfsId>
+where
+	C: Cache<LfsId> + Clone + 'static,
+	R: Resolver<LfsId>,
+	LfsId: sp_lfs_core::LfsId + 'static,
 {
 	type Response = Response<Body>;
 	type Error = http::Error;
-	type Future = future::Ready<Result<Self::Response, Self::Error>>;
+	type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
 	fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
 		Poll::Ready(Ok(()))
 	}
 
 	fn call(&mut self, req: Request<Body>) -> Self::Future {
-		if let Some(it) = self.resolver.resolve(req.uri().clone()) {
-			if let Some(key) = it
-				.filter(|key| self.cache.exists(key).unwrap_or(false))
-				.next()
-			{
-				if Some(key.clone())
-					== req
-						.headers()
-						.get(header::IF_NONE_MATCH)
-						.and_then(|l| b64decode::<LfsId>(l.as_bytes()))
-				{
-					return future::ok(
-						Response::builder()
-							.status(StatusCode::NOT_MODIFIED)
-							.body(Body::empty())
-							.expect("Empty doesn't fail"),
-					);
-				}
-				return future::ok(match self.cache.get(&key) {
-					Ok(data) => Response::builder()
-						.status(StatusCode::OK)
-						.header(header::ETAG, b64encode(key))
-						.body(data.into())
-						.expect("Building this simple response doesn't fail. qed"),
-					Err(_) => Response::builder()
-						.status(StatusCode::INTERNAL_SERVER_ERROR)
-						.body(Body::from(format!(
-							"Internal Server error key {:?} found, but couldn't be read.",
-							key
-						)))
-						.expect("Building this simple response doesn't fail. qed"),
-				});
-			}
+		let header_timer = self.header_timer.clone();
+		if let Some(timer) = header_timer.as_ref() {
+			timer.received();
 		}
-		future::ok(not_found())
+		let started = Instant::now();
+		let method = req.method().clone();
+		let path = req
+			.uri()
+			.path_and_query()
+			.map(|p| p.as_str().to_owned())
+			.unwrap_or_default();
+		let origin = req.headers().get(header::ORIGIN).cloned();
+		let headers = self.headers.clone();
+		let access_log = self.access_log.clone();
+		let response = match (req.method(), req.uri().path()) {
+			_ if !self.limiter.allow(self.remote) => future::ok(too_many_requests()).boxed(),
+			(&Method::OPTIONS, _) => future::ok(self.headers.preflight(&req)).boxed(),
+			(&Method::POST, upload::UPLOAD_PATH) | (&Method::PUT, upload::UPLOAD_PATH) => {
+				let cache = self.cache.clone();
+				let uploader = self.uploader.clone();
+				async move { upload::handle(cache, &uploader, req).await }.boxed()
+			}
+			_ => future::ok(self.serve(req)).boxed(),
+		};
+		let response = match self.limiter.request_timeout() {
+			Some(timeout) => tokio::time::timeout(timeout, response)
+				.map(|r| r.unwrap_or_else(|_| Ok(request_timeout())))
+				.boxed(),
+			None => response,
+		};
+		response
+			.map(move |res| {
+				if let Some(timer) = header_timer {
+					timer.responded();
+				}
+				res
+			})
+			.map_ok(move |mut res| {
+				headers.apply(origin.as_ref(), &mut res);
+				access_log.log(&method, &path, &res, started);
+				res
+			})
+			.boxed()
 	}
 }
 
-struct MakeSvc<C, R, L>(C, R, PhantomData<L>);
-impl<C, R, L> MakeSvc<C, R, L> {
-	fn new(cache: C, resolver: R) -> Self {
-		Self(cache, resolver, Default::default())
-	}
-}
+/// Hands out a copy of the current `LfsServer` for every connection
+struct MakeSvc<C, R, L: sp_lfs_core::LfsId>(Arc<RwLock<LfsServer<C, R, L>>>);
 
-impl<C, R, L, T> Service<T> for MakeSvc<C, R, L>
+impl<'a, C, R, L> Service<&'a Connection> for MakeSvc<C, R, L>
 where
 	C: Cache<L> + Clone + Send,
 	R: Resolver<L> + Clone + Send,
@@ -110,23 +389,163 @@ where
 		Ok(()).into()
 	}
 
-	fn call(&mut self, _: T) -> Self::Future {
-		future::ok(LfsServer::new(self.0.clone(), self.1.clone()))
+	fn call(&mut self, connection: &'a Connection) -> Self::Future {
+		let mut server = self.0.read().clone();
+		server.remote = Some(connection.remote_addr().ip());
+		server.header_timer = Some(connection.header_timer());
+		future::ok(server)
 	}
 }
 
-pub async fn start_server<C, R, LfsId>(cache: C, resolver: R) -> ()
+/// How often `ServerHandle::reload_on_change` checks for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Builds the resolver for a configuration, e.g. a `Router` of its `routes`
+pub type ResolverFactory<R> = Arc<dyn Fn(&ServerConfig) -> Result<R, String> + Send + Sync>;
+
+/// Controls a running server
+pub struct ServerHandle<C, R, L: sp_lfs_core::LfsId> {
+	listen: SocketAddr,
+	cache: C,
+	resolver_for: ResolverFactory<R>,
+	/// Copied for every new connection
+	prototype: Arc<RwLock<LfsServer<C, R, L>>>,
+	/// Shared with the incoming connections
+	limiter: Arc<RwLock<Arc<Limiter>>>,
+	shutdown: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+}
+
+impl<C, R, L> Clone for ServerHandle<C, R, L>
 where
-	C: Cache<LfsId> + Clone + 'static + Send,
-	R: Resolver<LfsId> + 'static + Send,
+	C: Clone,
+	R: Clone,
+	L: sp_lfs_core::LfsId,
+{
+	fn clone(&self) -> Self {
+		Self {
+			listen: self.listen,
+			cache: self.cache.clone(),
+			resolver_for: self.resolver_for.clone(),
+			prototype: self.prototype.clone(),
+			limiter: self.limiter.clone(),
+			shutdown: self.shutdown.clone(),
+		}
+	}
+}
+
+impl<C, R, LfsId> ServerHandle<C, R, LfsId>
+where
+	C: Cache<LfsId> + Clone + Send + Sync + 'static,
+	R: Resolver<LfsId> + Send + Sync + 'static,
 	LfsId: sp_lfs_core::LfsId + 'static,
 {
-	// This is our socket address...
-	let addr = ([127, 0, 0, 1], 8080).into();
-	let service = MakeSvc::new(cache, resolver);
+	/// Stop accepting connections and close the open ones once
+	/// the requests in flight are answered
+	pub fn shutdown(&self) {
+		if let Some(sender) = self.shutdown.lock().take() {
+			let _ = sender.send(());
+		}
+	}
 
-	let server = Server::bind(&addr).serve(service);
-	if let Err(e) = server.await {
-		println!("server error: {}", e);
+	/// Serve new connections according to `config`, with a resolver built for it,
+	/// open connections keep the configuration they were accepted with, but count
+	/// against the new connection caps. The listen address can't be changed.
+	pub fn reconfigure(&self, config: ServerConfig) -> Result<(), String> {
+		if config.listen != self.listen {
+			return Err(format!(
+				"Changing the listen address from {} to {} requires a restart",
+				self.listen, config.listen
+			));
+		}
+		let resolver = (self.resolver_for)(&config)?;
+		let mut server = LfsServer::new(self.cache.clone(), resolver, config)?;
+		server.limiter = Arc::new(Limiter::succeeding(
+			&self.limiter.read(),
+			&server.config.limits,
+		));
+		*self.limiter.write() = server.limiter.clone();
+		*self.prototype.write() = server;
+		Ok(())
+	}
+
+	/// Reconfigure the server whenever the file at `path` changes, reading the
+	/// configuration with `load`. Runs until dropped.
+	pub fn reload_on_change<F>(&self, path: PathBuf, load: F) -> impl Future<Output = ()> + Send
+	where
+		F: Fn(&Path) -> Result<ServerConfig, String> + Send + 'static,
+	{
+		let handle = self.clone();
+		async move {
+			let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+			let mut last = modified(&path);
+			let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+			loop {
+				interval.tick().await;
+				let now = modified(&path);
+				if now == last {
+					continue;
+				}
+				last = now;
+				match load(&path).and_then(|config| handle.reconfigure(config)) {
+					Ok(()) => info!("Reloaded the LFS HTTP server configuration"),
+					Err(e) => warn!("Keeping the LFS HTTP server configuration: {}", e),
+				}
+			}
+		}
 	}
 }
+
+/// Bind to the configured address and build the server, with the resolver
+/// `resolver_for` builds for the configuration. Fails right away if either isn't
+/// possible. Returns the handle to control the server and the future running it,
+/// which shuts the server down gracefully once `exit` resolves.
+pub fn start_server<C, R, F, LfsId, E>(
+	config: ServerConfig,
+	cache: C,
+	resolver_for: F,
+	exit: E,
+) -> Result<(ServerHandle<C, R, LfsId>, impl Future<Output = ()> + Send), String>
+where
+	C: Cache<LfsId> + Clone + Send + Sync + 'static,
+	R: Resolver<LfsId> + Send + Sync + 'static,
+	F: Fn(&ServerConfig) -> Result<R, String> + Send + Sync + 'static,
+	LfsId: sp_lfs_core::LfsId + 'static,
+	E: Future<Output = ()> + Send + 'static,
+{
+	let listen = config.listen;
+	let listener = TcpListener::bind(listen)
+		.and_then(|l| l.set_nonblocking(true).map(|_| l))
+		.map_err(|e| format!("Could not listen on {}: {}", listen, e))?;
+	let resolver = resolver_for(&config)?;
+	let server = LfsServer::new(cache.clone(), resolver, config)?;
+	let (sender, receiver) = oneshot::channel();
+
+	let handle = ServerHandle {
+		listen,
+		cache,
+		resolver_for: Arc::new(resolver_for),
+		limiter: Arc::new(RwLock::new(server.limiter.clone())),
+		prototype: Arc::new(RwLock::new(server)),
+		shutdown: Arc::new(Mutex::new(Some(sender))),
+	};
+
+	let limiter = handle.limiter.clone();
+	let prototype = handle.prototype.clone();
+	let task = async move {
+		let listener = match tokio::net::TcpListener::from_std(listener) {
+			Ok(listener) => listener,
+			Err(e) => {
+				error!("Could not listen on {}: {}", listen, e);
+				return;
+			}
+		};
+		let signal = future::select(receiver, exit.boxed()).map(|_| ());
+		let server = Server::builder(LimitedIncoming::new(listener, limiter))
+			.serve(MakeSvc(prototype))
+			.with_graceful_shutdown(signal);
+		if let Err(e) = server.await {
+			error!("LFS HTTP server error: {}", e);
+		}
+	};
+	Ok((handle, task))
+}
@@ -0,0 +1,396 @@
+use hyper::server::accept::Accept;
+use log::warn;
+use lru::LruCache;
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{delay_for, delay_until, Delay};
+
+use crate::config::{Bucket, LimitsConfig, Tokens};
+
+/// How many addresses to keep track of the request rate for
+const TRACKED_ADDRESSES: usize = 10_000;
+/// How long to wait before accepting again after failing to, e.g. as we ran out of file handles
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Open connections in total and per address
+#[derive(Default)]
+struct Connections {
+	total: usize,
+	per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Enforces the configured request rates and connection caps
+pub struct Limiter {
+	global: Option<Bucket>,
+	per_ip: Option<(f64, f64)>,
+	buckets: Mutex<LruCache<IpAddr, Tokens>>,
+	max_connections: usize,
+	max_connections_per_ip: usize,
+	/// Shared with the limiters replacing this one, as the connections stay open
+	connections: Arc<Mutex<Connections>>,
+	idle_timeout: Option<Duration>,
+	header_timeout: Option<Duration>,
+	request_timeout: Option<Duration>,
+}
+
+impl Limiter {
+	pub fn new(config: &LimitsConfig) -> Self {
+		let per_ip = if config.per_ip_rate > 0.0 {
+			Some((config.per_ip_rate, f64::from(config.per_ip_burst.max(1))))
+		} else {
+			None
+		};
+		let seconds = |s| {
+			if s > 0 {
+				Some(Duration::from_secs(s))
+			} else {
+				None
+			}
+		};
+		Limiter {
+			global: Bucket::new(config.global_rate, config.global_burst),
+			per_ip,
+			buckets: Mutex::new(LruCache::new(TRACKED_ADDRESSES)),
+			max_connections: config.max_connections,
+			max_connections_per_ip: config.max_connections_per_ip,
+			connections: Default::default(),
+			idle_timeout: seconds(config.idle_timeout),
+			header_timeout: seconds(config.header_timeout),
+			request_timeout: seconds(config.request_timeout),
+		}
+	}
+
+	/// Like `new`, counting the connections `previous` accepted against the caps,
+	/// as those stay open when the server is reconfigured
+	pub fn succeeding(previous: &Limiter, config: &LimitsConfig) -> Self {
+		Limiter {
+			connections: previous.connections.clone(),
+			..Limiter::new(config)
+		}
+	}
+
+	/// How long handling a request may take, if limited
+	pub fn request_timeout(&self) -> Option<Duration> {
+		self.request_timeout
+	}
+
+	/// Whether a request from `ip` is within the rate limits
+	pub fn allow(&self, ip: Option<IpAddr>) -> bool {
+		if let (Some((rate, burst)), Some(ip)) = (self.per_ip, ip) {
+			let mut buckets = self.buckets.lock();
+			let allowed = match buckets.get_mut(&ip) {
+				Some(tokens) => tokens.take(rate, burst),
+				None => {
+					let mut tokens = Tokens::full(burst);
+					let allowed = tokens.take(rate, burst);
+					buckets.put(ip, tokens);
+					allowed
+				}
+			};
+			if !allowed {
+				return false;
+			}
+		}
+		self.global.as_ref().map(|b| b.take()).unwrap_or(true)
+	}
+
+	/// Register a new connection from `ip`, `None` if that exceeds the caps.
+	/// The connection counts until the returned guard is dropped.
+	fn connect(&self, ip: IpAddr) -> Option<ConnectionGuard> {
+		let mut connections = self.connections.lock();
+		let of_ip = connections.per_ip.get(&ip).cloned().unwrap_or(0);
+		if (self.max_connections > 0 && connections.total >= self.max_connections)
+			|| (self.max_connections_per_ip > 0 && of_ip >= self.max_connections_per_ip)
+		{
+			return None;
+		}
+		connections.total += 1;
+		connections.per_ip.insert(ip, of_ip + 1);
+		Some(ConnectionGuard {
+			connections: self.connections.clone(),
+			ip,
+		})
+	}
+
+	/// Accept the incoming `stream`, if within the connection caps
+	fn accept(&self, stream: TcpStream, remote: SocketAddr) -> Option<Connection> {
+		let guard = self.connect(remote.ip())?;
+		Some(Connection {
+			stream,
+			remote,
+			idle_timeout: self.idle_timeout,
+			deadline: self.idle_timeout.map(delay_for),
+			header_timer: HeaderTimer::new(self.header_timeout),
+			header_deadline: None,
+			_guard: guard,
+		})
+	}
+}
+
+/// Incoming connections, closing the ones exceeding the connection caps right away
+pub struct LimitedIncoming {
+	listener: TcpListener,
+	/// Replaced when the server is reconfigured
+	limiter: Arc<RwLock<Arc<Limiter>>>,
+	/// Wait before accepting again
+	error_delay: Option<Delay>,
+}
+
+impl LimitedIncoming {
+	pub fn new(listener: TcpListener, limiter: Arc<RwLock<Arc<Limiter>>>) -> Self {
+		LimitedIncoming {
+			listener,
+			limiter,
+			error_delay: None,
+		}
+	}
+}
+
+/// Errors concerning a single connection only, rather than the listener
+fn is_connection_error(e: &io::Error) -> bool {
+	match e.kind() {
+		io::ErrorKind::ConnectionRefused
+		| io::ErrorKind::ConnectionAborted
+		| io::ErrorKind::ConnectionReset => true,
+		_ => false,
+	}
+}
+
+impl Accept for LimitedIncoming {
+	type Conn = Connection;
+	type Error = io::Error;
+
+	fn poll_accept(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+	) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+		let this = self.get_mut();
+		if let Some(delay) = this.error_delay.as_mut() {
+			if Pin::new(delay).poll(cx).is_pending() {
+				return Poll::Pending;
+			}
+			this.error_delay = None;
+		}
+		loop {
+			match this.listener.poll_accept(cx) {
+				Poll::Ready(Ok((stream, remote))) => {
+					let limiter = this.limiter.read().clone();
+					if let Some(connection) = limiter.accept(stream, remote) {
+						return Poll::Ready(Some(Ok(connection)));
+					}
+					// dropping the stream closes it, go on with the next one
+				}
+				Poll::Ready(Err(ref e)) if is_connection_error(e) => {}
+				Poll::Ready(Err(e)) => {
+					// failing here would stop the server, try again later instead
+					warn!("Failed to accept connection: {}", e);
+					let mut delay = delay_for(ACCEPT_ERROR_DELAY);
+					if Pin::new(&mut delay).poll(cx).is_pending() {
+						this.error_delay = Some(delay);
+						return Poll::Pending;
+					}
+				}
+				Poll::Pending => return Poll::Pending,
+			}
+		}
+	}
+}
+
+/// Counts as an open connection as long as it lives
+struct ConnectionGuard {
+	connections: Arc<Mutex<Connections>>,
+	ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+	fn drop(&mut self) {
+		let mut connections = self.connections.lock();
+		connections.total = connections.total.saturating_sub(1);
+		let remove = match connections.per_ip.get_mut(&self.ip) {
+			Some(count) => {
+				*count = count.saturating_sub(1);
+				*count == 0
+			}
+			None => false,
+		};
+		if remove {
+			connections.per_ip.remove(&self.ip);
+		}
+	}
+}
+
+/// Where a connection is in receiving a request
+enum Phase {
+	/// Waiting for the headers of a request, which must be complete by the deadline,
+	/// if any bytes of them were received yet
+	Headers(Option<tokio::time::Instant>),
+	/// The headers were received, the request is handled
+	Handling,
+}
+
+/// Limits how long receiving the headers of a request may take, counted from accepting the
+/// connection, or the first byte received after the previous request, to the headers being
+/// complete. Unlike the idle timeout, a client sending a byte now and then doesn't reset it.
+#[derive(Clone)]
+pub struct HeaderTimer {
+	timeout: Option<Duration>,
+	phase: Arc<Mutex<Phase>>,
+}
+
+impl HeaderTimer {
+	fn new(timeout: Option<Duration>) -> Self {
+		let deadline = timeout.map(|t| tokio::time::Instant::now() + t);
+		HeaderTimer {
+			timeout,
+			phase: Arc::new(Mutex::new(Phase::Headers(deadline))),
+		}
+	}
+
+	/// The headers of a request are complete, it is handled now
+	pub fn received(&self) {
+		*self.phase.lock() = Phase::Handling;
+	}
+
+	/// The request was answered, the headers of the next one are awaited
+	pub fn responded(&self) {
+		*self.phase.lock() = Phase::Headers(None);
+	}
+
+	/// The deadline for the headers of the current request, if they are awaited
+	fn deadline(&self) -> Option<tokio::time::Instant> {
+		match *self.phase.lock() {
+			Phase::Headers(deadline) => deadline,
+			Phase::Handling => None,
+		}
+	}
+
+	/// Bytes were received, starting the deadline if they begin the next request
+	fn bytes_received(&self) -> Option<tokio::time::Instant> {
+		let timeout = self.timeout?;
+		let mut phase = self.phase.lock();
+		match *phase {
+			Phase::Headers(None) => {
+				let deadline = tokio::time::Instant::now() + timeout;
+				*phase = Phase::Headers(Some(deadline));
+				Some(deadline)
+			}
+			Phase::Headers(deadline) => deadline,
+			Phase::Handling => None,
+		}
+	}
+}
+
+fn header_timeout() -> io::Error {
+	io::Error::new(io::ErrorKind::TimedOut, "request headers took too long")
+}
+
+/// An accepted connection, closed if nothing is sent or received for the idle timeout,
+/// or the headers of a request aren't received in time
+pub struct Connection {
+	stream: TcpStream,
+	remote: SocketAddr,
+	idle_timeout: Option<Duration>,
+	deadline: Option<Delay>,
+	header_timer: HeaderTimer,
+	/// Wakes the connection up once the headers are overdue
+	header_deadline: Option<Delay>,
+	_guard: ConnectionGuard,
+}
+
+impl Connection {
+	/// The address of the peer
+	pub fn remote_addr(&self) -> SocketAddr {
+		self.remote
+	}
+
+	/// Tracks the headers of the requests received on this connection
+	pub fn header_timer(&self) -> HeaderTimer {
+		self.header_timer.clone()
+	}
+
+	/// Whether the headers awaited are overdue, registering to be woken up once they are
+	fn headers_overdue(&mut self, cx: &mut Context<'_>) -> bool {
+		let deadline = match self.header_timer.deadline() {
+			Some(deadline) => deadline,
+			None => return false,
+		};
+		let delay = self
+			.header_deadline
+			.get_or_insert_with(|| delay_until(deadline));
+		if delay.deadline() != deadline {
+			delay.reset(deadline);
+		}
+		Pin::new(delay).poll(cx).is_ready()
+	}
+
+	/// Something was read or written, the connection isn't idle
+	fn active(&mut self) {
+		if let (Some(deadline), Some(timeout)) = (self.deadline.as_mut(), self.idle_timeout) {
+			deadline.reset(tokio::time::Instant::now() + timeout);
+		}
+	}
+}
+
+impl AsyncRead for Connection {
+	fn poll_read(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &mut [u8],
+	) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		match Pin::new(&mut this.stream).poll_read(cx, buf) {
+			Poll::Ready(Ok(read)) if read > 0 => match this.header_timer.bytes_received() {
+				Some(deadline) if deadline <= tokio::time::Instant::now() => {
+					Poll::Ready(Err(header_timeout()))
+				}
+				_ => {
+					this.active();
+					Poll::Ready(Ok(read))
+				}
+			},
+			Poll::Ready(result) => {
+				this.active();
+				Poll::Ready(result)
+			}
+			Poll::Pending if this.headers_overdue(cx) => Poll::Ready(Err(header_timeout())),
+			Poll::Pending => match this.deadline.as_mut() {
+				Some(deadline) if Pin::new(deadline).poll(cx).is_ready() => Poll::Ready(Err(
+					io::Error::new(io::ErrorKind::TimedOut, "connection idle for too long"),
+				)),
+				_ => Poll::Pending,
+			},
+		}
+	}
+}
+
+impl AsyncWrite for Connection {
+	fn poll_write(
+		self: Pin<&mut Self>,
+		cx: &mut Context<'_>,
+		buf: &[u8],
+	) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		let result = Pin::new(&mut this.stream).poll_write(cx, buf);
+		if result.is_ready() {
+			this.active();
+		}
+		result
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+	}
+}
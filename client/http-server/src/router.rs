@@ -0,0 +1,240 @@
+use hyper::{HeaderMap, Uri};
+use sp_lfs_core::LfsId;
+use std::sync::Arc;
+
+use crate::config::RouteConfig;
+use crate::traits::{Resolution, Resolver};
+
+/// Name of the `()` resolver, serving base64 encoded `LfsId`s directly
+pub const RAW_RESOLVER: &str = "raw";
+
+/// The `uri` with the path `prefix` removed, if it is mounted under it
+fn strip_prefix(prefix: &str, uri: &Uri) -> Option<Uri> {
+	let base = prefix.trim_end_matches('/');
+	let path = uri.path();
+	let rest = if path == base {
+		"/"
+	} else if path.starts_with(base) && path[base.len()..].starts_with('/') {
+		&path[base.len()..]
+	} else {
+		return None;
+	};
+	let path_and_query = match uri.query() {
+		Some(query) => format!("{}?{}", rest, query),
+		None => rest.to_owned(),
+	};
+	let mut parts = uri.clone().into_parts();
+	parts.path_and_query = Some(path_and_query.parse().ok()?);
+	Uri::from_parts(parts).ok()
+}
+
+/// Yields the resolutions of a mounted resolver, with its redirects
+/// pointing below the mount point again
+pub struct MountIterator<I> {
+	base: String,
+	inner: I,
+}
+
+impl<L, I> Iterator for MountIterator<I>
+where
+	I: Iterator<Item = Resolution<L>>,
+{
+	type Item = Resolution<L>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.inner.next().map(|resolution| match resolution {
+			Resolution::Redirect(status, location) if location.starts_with('/') => {
+				Resolution::Redirect(status, format!("{}{}", self.base, location))
+			}
+			other => other,
+		})
+	}
+}
+
+/// Serve the `inner` resolver under the path `prefix`, e.g. `/u/`
+#[derive(Clone)]
+pub struct Mount<R> {
+	prefix: String,
+	inner: R,
+}
+
+impl<R> Mount<R> {
+	pub fn new(prefix: &str, inner: R) -> Self {
+		Mount {
+			prefix: prefix.to_owned(),
+			inner,
+		}
+	}
+}
+
+impl<L, R> Resolver<L> for Mount<R>
+where
+	L: LfsId,
+	R: Resolver<L>,
+{
+	type Iterator = MountIterator<R::Iterator>;
+
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator> {
+		let inner_uri = strip_prefix(&self.prefix, &uri)?;
+		self.inner
+			.resolve(inner_uri, headers)
+			.map(|inner| MountIterator {
+				base: self.prefix.trim_end_matches('/').to_owned(),
+				inner,
+			})
+	}
+}
+
+/// Object safe version of `Resolver`, to combine resolvers of different types at runtime
+pub trait DynResolver<L>: Send + Sync {
+	fn resolve_boxed(
+		&self,
+		uri: Uri,
+		headers: &HeaderMap,
+	) -> Option<Box<dyn Iterator<Item = Resolution<L>>>>;
+}
+
+impl<L, R> DynResolver<L> for R
+where
+	L: LfsId + 'static,
+	R: Resolver<L> + Send + Sync,
+	R::Iterator: 'static,
+{
+	fn resolve_boxed(
+		&self,
+		uri: Uri,
+		headers: &HeaderMap,
+	) -> Option<Box<dyn Iterator<Item = Resolution<L>>>> {
+		self.resolve(uri, headers)
+			.map(|it| Box::new(it) as Box<dyn Iterator<Item = Resolution<L>>>)
+	}
+}
+
+impl<L: LfsId + 'static> Resolver<L> for Arc<dyn DynResolver<L>> {
+	type Iterator = Box<dyn Iterator<Item = Resolution<L>>>;
+
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator> {
+		self.resolve_boxed(uri, headers)
+	}
+}
+
+/// Resolves paths by asking the resolvers mounted at matching prefixes, in order
+pub struct Router<L> {
+	routes: Arc<Vec<Mount<Arc<dyn DynResolver<L>>>>>,
+}
+
+impl<L> Clone for Router<L> {
+	fn clone(&self) -> Self {
+		Router {
+			routes: self.routes.clone(),
+		}
+	}
+}
+
+impl<L: LfsId + 'static> Default for Router<L> {
+	fn default() -> Self {
+		Router::new()
+	}
+}
+
+impl<L: LfsId + 'static> Router<L> {
+	pub fn new() -> Self {
+		Router {
+			routes: Arc::new(Vec::new()),
+		}
+	}
+
+	/// Add `resolver` under the path `prefix`, after all routes added before
+	pub fn mount<R>(self, prefix: &str, resolver: R) -> Self
+	where
+		R: DynResolver<L> + 'static,
+	{
+		self.mount_shared(prefix, Arc::new(resolver))
+	}
+
+	fn mount_shared(self, prefix: &str, resolver: Arc<dyn DynResolver<L>>) -> Self {
+		let mut routes = Arc::try_unwrap(self.routes).unwrap_or_else(|r| (*r).clone());
+		routes.push(Mount::new(prefix, resolver));
+		Router {
+			routes: Arc::new(routes),
+		}
+	}
+
+	/// Build the router for the configured `routes`, looking up the resolvers by name.
+	/// Fails if no resolver of a configured name is available.
+	pub fn from_config<F>(routes: &[RouteConfig], mut resolver_for: F) -> Result<Self, String>
+	where
+		F: FnMut(&str) -> Option<Arc<dyn DynResolver<L>>>,
+	{
+		routes.iter().try_fold(Router::new(), |router, route| {
+			let resolver = resolver_for(&route.resolver).ok_or_else(|| {
+				format!("Unknown resolver {} for {}", route.resolver, route.prefix)
+			})?;
+			Ok(router.mount_shared(&route.prefix, resolver))
+		})
+	}
+}
+
+impl<L: LfsId + 'static> Resolver<L> for Router<L> {
+	type Iterator = std::iter::Flatten<
+		std::vec::IntoIter<MountIterator<Box<dyn Iterator<Item = Resolution<L>>>>>,
+	>;
+
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator> {
+		let matching = self
+			.routes
+			.iter()
+			.filter_map(|route| route.resolve(uri.clone(), headers))
+			.collect::<Vec<_>>();
+		if matching.is_empty() {
+			None
+		} else {
+			Some(matching.into_iter().flatten())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn stripped(prefix: &str, uri: &str) -> Option<String> {
+		strip_prefix(prefix, &uri.parse().unwrap()).map(|u| u.to_string())
+	}
+
+	#[test]
+	fn strips_prefixes_with_and_without_trailing_slash() {
+		assert_eq!(
+			stripped("/u/", "/u/alice/index.html"),
+			Some("/alice/index.html".into())
+		);
+		assert_eq!(
+			stripped("/u", "/u/alice/index.html"),
+			Some("/alice/index.html".into())
+		);
+		assert_eq!(stripped("/u/", "/u"), Some("/".into()));
+		assert_eq!(stripped("/u/", "/u/"), Some("/".into()));
+	}
+
+	#[test]
+	fn keeps_paths_under_the_root() {
+		assert_eq!(stripped("/", "/"), Some("/".into()));
+		assert_eq!(stripped("/", "/a/b.html"), Some("/a/b.html".into()));
+	}
+
+	#[test]
+	fn keeps_the_query() {
+		assert_eq!(
+			stripped("/u/", "/u/a.html?at=0x12"),
+			Some("/a.html?at=0x12".into())
+		);
+		assert_eq!(stripped("/u/", "/u?at=0x12"), Some("/?at=0x12".into()));
+	}
+
+	#[test]
+	fn ignores_paths_not_under_the_prefix() {
+		assert_eq!(stripped("/u/", "/users/a.html"), None);
+		assert_eq!(stripped("/u/", "/lfs/u/a.html"), None);
+		assert_eq!(stripped("/lfs/", "/"), None);
+	}
+}
@@ -0,0 +1,272 @@
+use hyper::StatusCode;
+use lru::LruCache;
+use parking_lot::Mutex;
+use sp_lfs_cache::RuntimeCacheInterface;
+use sp_lfs_core::LfsReference;
+use std::sync::Arc;
+
+/// How many parsed rule files to keep in memory
+const DEFAULT_CACHE_ENTRIES: usize = 64;
+/// Placeholder in targets for the part of the path matched by a trailing `*`
+const SPLAT: &str = ":splat";
+/// Headers sites may set via rules, all sites share an origin so anything touching it, like
+/// `Set-Cookie`, `Service-Worker-Allowed` or `Access-Control-*`, must stay with the server
+const ALLOWED_HEADERS: &[&str] = &[
+	"cache-control",
+	"content-language",
+	"content-security-policy",
+	"referrer-policy",
+	"x-content-type-options",
+	"x-frame-options",
+];
+
+#[derive(Clone, Debug)]
+enum Action {
+	/// Redirect to the target with the status
+	Redirect(StatusCode, String),
+	/// Serve the target instead, without telling the client
+	Rewrite(String),
+	/// Send the header along
+	Header(String, String),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+	/// The path to match, without the trailing `*`
+	pattern: String,
+	/// Match any path starting with `pattern`
+	prefix: bool,
+	action: Action,
+}
+
+impl Rule {
+	/// Parse a line of `<from> <to> [status]` or `<from> <Header-Name>: <value>`
+	fn parse(line: &str) -> Option<Self> {
+		let mut parts = line.split_whitespace();
+		let from = parts.next()?;
+		let second = parts.next()?;
+
+		let action = if second.ends_with(':') {
+			let name = second.trim_end_matches(':');
+			if !ALLOWED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+				return None;
+			}
+			let value = parts.collect::<Vec<_>>().join(" ");
+			Action::Header(name.to_owned(), value)
+		} else {
+			let status = match parts.next() {
+				Some(s) => s.parse::<u16>().ok()?,
+				None => 301,
+			};
+			match status {
+				200 => Action::Rewrite(second.to_owned()),
+				301 | 302 | 303 | 307 | 308 => {
+					Action::Redirect(StatusCode::from_u16(status).ok()?, second.to_owned())
+				}
+				_ => return None,
+			}
+		};
+
+		let (pattern, prefix) = if from.ends_with('*') {
+			(from.trim_end_matches('*'), true)
+		} else {
+			(from, false)
+		};
+		Some(Rule {
+			pattern: pattern.to_owned(),
+			prefix,
+			action,
+		})
+	}
+
+	/// The part matched by `*`, empty for exact matches, if `path` matches
+	fn matches<'a>(&self, path: &'a str) -> Option<&'a str> {
+		if self.prefix {
+			if path.starts_with(&self.pattern) {
+				Some(&path[self.pattern.len()..])
+			} else {
+				None
+			}
+		} else if path == self.pattern {
+			Some("")
+		} else {
+			None
+		}
+	}
+}
+
+/// What the rules say about a path
+#[derive(Default, Debug)]
+pub struct Applied {
+	/// Redirect with the status to this location
+	pub redirect: Option<(StatusCode, String)>,
+	/// Serve this path instead
+	pub rewrite: Option<String>,
+	/// Headers to send with the response
+	pub headers: Vec<(String, String)>,
+}
+
+/// Redirect, rewrite and header rules of a site, one per line:
+///
+/// ```text
+/// # moved pages, `301` if no status is given
+/// /old.html        /new.html
+/// /blog/*          /posts/:splat   302
+/// # serve the index page for all paths of the single page application
+/// /app/*           /app/index.html 200
+/// /assets/*        Cache-Control: public, max-age=3600
+/// ```
+///
+/// The first matching redirect or rewrite applies, as do all matching header rules.
+/// Only the headers in `ALLOWED_HEADERS` may be set, rules for others are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+	rules: Vec<Rule>,
+}
+
+impl Rules {
+	/// Parse the rules, skipping empty lines, comments and invalid rules
+	pub fn parse(input: &str) -> Self {
+		Rules {
+			rules: input
+				.lines()
+				.map(|l| l.trim())
+				.filter(|l| !l.is_empty() && !l.starts_with('#'))
+				.filter_map(Rule::parse)
+				.collect(),
+		}
+	}
+
+	/// Apply the rules to `path`
+	pub fn apply(&self, path: &str) -> Applied {
+		let mut applied = Applied::default();
+		for rule in self.rules.iter() {
+			let splat = match rule.matches(path) {
+				Some(splat) => splat,
+				None => continue,
+			};
+			let done = applied.redirect.is_some() || applied.rewrite.is_some();
+			match rule.action {
+				Action::Header(ref name, ref value) => {
+					applied.headers.push((name.clone(), value.clone()))
+				}
+				Action::Redirect(status, ref target) if !done => {
+					applied.redirect = Some((status, target.replace(SPLAT, splat)))
+				}
+				Action::Rewrite(ref target) if !done => {
+					applied.rewrite = Some(target.replace(SPLAT, splat))
+				}
+				_ => {}
+			}
+		}
+		applied
+	}
+}
+
+/// Parses rules stored in LFS once and keeps them around, by their reference
+pub struct RulesCache {
+	cache: Box<dyn RuntimeCacheInterface>,
+	parsed: Mutex<LruCache<LfsReference, Arc<Rules>>>,
+}
+
+impl RulesCache {
+	pub fn new(cache: Box<dyn RuntimeCacheInterface>) -> Self {
+		RulesCache {
+			cache,
+			parsed: Mutex::new(LruCache::new(DEFAULT_CACHE_ENTRIES)),
+		}
+	}
+
+	/// The rules stored at `reference`, if its data is available locally
+	pub fn get(&self, reference: &LfsReference) -> Option<Arc<Rules>> {
+		if let Some(rules) = self.parsed.lock().get(reference) {
+			return Some(rules.clone());
+		}
+		let data = self.cache.get(reference).ok()?;
+		let rules = Arc::new(Rules::parse(&String::from_utf8_lossy(&data)));
+		self.parsed.lock().put(reference.clone(), rules.clone());
+		Some(rules)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn redirects_exact_paths() {
+		let rules = Rules::parse("/old.html /new.html\n/tmp /temporary 302");
+		let applied = rules.apply("/old.html");
+		assert_eq!(
+			applied.redirect,
+			Some((StatusCode::MOVED_PERMANENTLY, "/new.html".into()))
+		);
+		assert!(rules.apply("/old.html/more").redirect.is_none());
+		assert_eq!(
+			rules.apply("/tmp").redirect,
+			Some((StatusCode::FOUND, "/temporary".into()))
+		);
+	}
+
+	#[test]
+	fn replaces_splat_of_prefix_rules() {
+		let rules = Rules::parse("/blog/* /posts/:splat 302\n/app/* /app/index.html 200");
+		assert_eq!(
+			rules.apply("/blog/2020/hello.html").redirect,
+			Some((StatusCode::FOUND, "/posts/2020/hello.html".into()))
+		);
+		assert_eq!(
+			rules.apply("/blog/").redirect,
+			Some((StatusCode::FOUND, "/posts/".into()))
+		);
+		assert_eq!(
+			rules.apply("/app/settings").rewrite,
+			Some("/app/index.html".into())
+		);
+		assert!(rules.apply("/blog").redirect.is_none());
+	}
+
+	#[test]
+	fn first_redirect_or_rewrite_wins() {
+		let rules = Rules::parse("/a/* /first 200\n/a/b /second 301");
+		let applied = rules.apply("/a/b");
+		assert_eq!(applied.rewrite, Some("/first".into()));
+		assert!(applied.redirect.is_none());
+	}
+
+	#[test]
+	fn skips_comments_and_invalid_rules() {
+		let rules = Rules::parse("# comment\n\n/a /b 404\n/c\n/d /e 200");
+		assert!(rules.apply("/a").redirect.is_none());
+		assert_eq!(rules.apply("/d").rewrite, Some("/e".into()));
+	}
+
+	#[test]
+	fn applies_all_matching_headers() {
+		let rules =
+			Rules::parse("/assets/* Cache-Control: public, max-age=3600\n/* X-Frame-Options: DENY");
+		assert_eq!(
+			rules.apply("/assets/logo.png").headers,
+			vec![
+				("Cache-Control".into(), "public, max-age=3600".into()),
+				("X-Frame-Options".into(), "DENY".into()),
+			]
+		);
+	}
+
+	#[test]
+	fn drops_headers_not_allowed() {
+		let rules = Rules::parse(
+			"/* Set-Cookie: session=1\n/* Service-Worker-Allowed: /\n\
+			 /* Access-Control-Allow-Origin: *\n/* content-type: text/html\n\
+			 /* content-security-policy: default-src 'self'",
+		);
+		assert_eq!(
+			rules.apply("/index.html").headers,
+			vec![(
+				"content-security-policy".into(),
+				"default-src 'self'".into()
+			)]
+		);
+	}
+}
@@ -1,23 +1,127 @@
 use crate::helpers::b64decode;
-use hyper::Uri;
+use hyper::{HeaderMap, StatusCode, Uri};
 use sp_lfs_core::LfsId;
+use std::time::SystemTime;
+
+/// What a resolved key is to the requested path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolvedKind {
+	/// The entry of the path itself
+	Exact,
+	/// A catch-all for paths without an entry, e.g. of a single page application
+	Fallback,
+	/// The page to show as the path doesn't exist
+	NotFound,
+}
+
+/// A key yielded by a `Resolver`, with the information
+/// on how long responses for it may be cached
+#[derive(Clone, Debug)]
+pub struct Resolved<L> {
+	/// The `LfsId` to look up
+	pub key: L,
+	/// The path is content-addressed and will always resolve to this key
+	pub immutable: bool,
+	/// When the path last changed to resolve to this key, if known
+	pub last_modified: Option<SystemTime>,
+	/// The key is known to be referenced, e.g. on chain, and thus expected
+	/// to become available, even if it isn't available locally (yet)
+	pub expected: bool,
+	/// The key is a not yet confirmed version of the path
+	pub preview: bool,
+	/// How the key relates to the path
+	pub kind: ResolvedKind,
+	/// Additional headers to send with the response
+	pub headers: Vec<(String, String)>,
+	/// The step of the resolver yielding the key, for logging
+	pub step: Option<&'static str>,
+}
+
+impl<L> Resolved<L> {
+	/// The key was given directly, e.g. as part of the path
+	pub fn immutable(key: L) -> Self {
+		Resolved {
+			key,
+			immutable: true,
+			last_modified: None,
+			expected: false,
+			preview: false,
+			kind: ResolvedKind::Exact,
+			headers: Vec::new(),
+			step: None,
+		}
+	}
+
+	/// The key was looked up and may change over time
+	pub fn mutable(key: L, last_modified: Option<SystemTime>) -> Self {
+		Resolved {
+			key,
+			immutable: false,
+			last_modified,
+			expected: false,
+			preview: false,
+			kind: ResolvedKind::Exact,
+			headers: Vec::new(),
+			step: None,
+		}
+	}
+}
+
+/// A candidate answer for a path
+#[derive(Clone, Debug)]
+pub enum Resolution<L> {
+	/// Serve the data of the key, if it is available
+	Key(Resolved<L>),
+	/// Redirect to the location with the given status
+	Redirect(StatusCode, String),
+	/// The path is a directory holding these entries
+	Listing(Vec<String>),
+}
 
 /// This can resolve a path into a set of
 /// LfsIds we'd like to check for
 pub trait Resolver<L: LfsId>: Clone {
-	/// The iterator this resolves to, must yield `LfsdId`s
-	type Iterator: core::iter::Iterator<Item = L>;
+	/// The iterator this resolves to, must yield `Resolution`s
+	type Iterator: core::iter::Iterator<Item = Resolution<L>>;
 
-	/// Given the path, yield the `LfsId`s to look up
-	fn resolve(&self, uri: Uri) -> Option<Self::Iterator>;
+	/// Given the path and the request headers, yield the `LfsId`s to look up
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator>;
 }
 
 /// Default implementation just takes the entire path,
 /// excluding the starting slash, and attempts to base64 decode that
 impl<L: LfsId> Resolver<L> for () {
-	type Iterator = std::vec::IntoIter<L>;
-	fn resolve(&self, uri: Uri) -> Option<Self::Iterator> {
+	type Iterator = std::vec::IntoIter<Resolution<L>>;
+	fn resolve(&self, uri: Uri, _headers: &HeaderMap) -> Option<Self::Iterator> {
 		let (_, pure_path) = uri.path().split_at(1);
-		b64decode::<L>(pure_path.as_bytes()).map(|id| vec![id].into_iter())
+		b64decode::<L>(pure_path.as_bytes())
+			.map(|id| vec![Resolution::Key(Resolved::immutable(id))].into_iter())
+	}
+}
+
+/// Chain two resolvers, yielding the resolutions of the second after the ones of the first
+impl<L, A, B> Resolver<L> for (A, B)
+where
+	L: LfsId,
+	A: Resolver<L>,
+	B: Resolver<L>,
+{
+	type Iterator = std::iter::Chain<
+		std::iter::Flatten<std::option::IntoIter<A::Iterator>>,
+		std::iter::Flatten<std::option::IntoIter<B::Iterator>>,
+	>;
+
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator> {
+		let first = self.0.resolve(uri.clone(), headers);
+		let second = self.1.resolve(uri, headers);
+		if first.is_none() && second.is_none() {
+			return None;
+		}
+		Some(
+			first
+				.into_iter()
+				.flatten()
+				.chain(second.into_iter().flatten()),
+		)
 	}
 }
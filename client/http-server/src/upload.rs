@@ -0,0 +1,150 @@
+use codec::Encode;
+use futures::StreamExt;
+use hyper::{header, http, Body, Request, Response, StatusCode};
+use sp_core::crypto::Ss58Codec;
+use sp_core::{sr25519, Pair};
+use sp_lfs_cache::Cache;
+use sp_lfs_core::LfsId;
+
+use crate::config::UploadConfig;
+use crate::helpers::b64encode;
+
+/// The path uploads are accepted at
+pub const UPLOAD_PATH: &str = "/lfs";
+/// Header holding the SS58 address of the uploading account
+pub const ACCOUNT_HEADER: &str = "x-lfs-account";
+/// Header holding the hex encoded sr25519 signature of the encoded `LfsId`
+pub const SIGNATURE_HEADER: &str = "x-lfs-signature";
+
+/// Checks and accepts uploads according to the `UploadConfig`
+pub struct Uploader {
+	enabled: bool,
+	max_size: u64,
+	require_signature: bool,
+	allowed: Vec<sr25519::Public>,
+}
+
+impl Uploader {
+	pub fn new(config: &UploadConfig) -> Result<Self, String> {
+		let allowed = config
+			.allowed_accounts
+			.iter()
+			.map(|a| {
+				sr25519::Public::from_ss58check(a)
+					.map_err(|e| format!("Invalid upload account {}: {:?}", a, e))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		Ok(Uploader {
+			enabled: config.enabled,
+			max_size: config.max_size,
+			require_signature: config.require_signature,
+			allowed,
+		})
+	}
+
+	/// The `account`, if it is allowed to upload
+	fn allowed_account(&self, account: &str) -> Option<sr25519::Public> {
+		sr25519::Public::from_ss58check(account)
+			.ok()
+			.filter(|who| self.allowed.contains(who))
+	}
+}
+
+/// Whether `who` signed `key` with the hex encoded `signature`
+fn is_signed_by<L: LfsId>(key: &L, who: &sr25519::Public, signature: &str) -> bool {
+	let signature = match hex::decode(signature.trim_start_matches("0x")) {
+		Ok(ref raw) if raw.len() == 64 => {
+			let mut sig = [0u8; 64];
+			sig.copy_from_slice(raw);
+			sr25519::Signature::from_raw(sig)
+		}
+		_ => return false,
+	};
+	sr25519::Pair::verify(&signature, key.encode(), who)
+}
+
+fn respond(status: StatusCode, msg: &'static str) -> Result<Response<Body>, http::Error> {
+	Response::builder().status(status).body(Body::from(msg))
+}
+
+/// Read the body of the request, store it in the cache and respond with the resulting key
+pub(crate) async fn handle<C, L>(
+	cache: C,
+	uploader: &Uploader,
+	req: Request<Body>,
+) -> Result<Response<Body>, http::Error>
+where
+	C: Cache<L>,
+	L: LfsId,
+{
+	if !uploader.enabled {
+		return respond(StatusCode::METHOD_NOT_ALLOWED, "Uploads are disabled");
+	}
+
+	let announced_size = req
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|l| l.to_str().ok())
+		.and_then(|l| l.parse::<u64>().ok());
+	if announced_size.unwrap_or(0) > uploader.max_size {
+		return respond(StatusCode::PAYLOAD_TOO_LARGE, "Upload too large");
+	}
+
+	let header_str = |name| {
+		req.headers()
+			.get(name)
+			.and_then(|v| v.to_str().ok())
+			.map(|v| v.to_owned())
+	};
+	// Refuse unauthorized uploads before reading their body; only the signature,
+	// which depends on the data, is checked once it was received
+	let credentials = if uploader.require_signature {
+		let account = header_str(ACCOUNT_HEADER).and_then(|a| uploader.allowed_account(&a));
+		match (account, header_str(SIGNATURE_HEADER)) {
+			(Some(who), Some(signature)) => Some((who, signature)),
+			_ => return respond(StatusCode::UNAUTHORIZED, "Upload not authorized"),
+		}
+	} else {
+		None
+	};
+
+	let mut body = req.into_body();
+	let mut data = Vec::with_capacity(announced_size.unwrap_or(0) as usize);
+	while let Some(chunk) = body.next().await {
+		match chunk {
+			Ok(chunk) => {
+				if (data.len() + chunk.len()) as u64 > uploader.max_size {
+					return respond(StatusCode::PAYLOAD_TOO_LARGE, "Upload too large");
+				}
+				data.extend_from_slice(&chunk);
+			}
+			Err(_) => return respond(StatusCode::BAD_REQUEST, "Reading upload failed"),
+		}
+	}
+
+	let key = match L::for_data(&data) {
+		Ok(key) => key,
+		Err(_) => return respond(StatusCode::BAD_REQUEST, "Could not compute key"),
+	};
+
+	if let Some((who, signature)) = credentials {
+		if !is_signed_by(&key, &who, &signature) {
+			return respond(StatusCode::UNAUTHORIZED, "Upload not authorized");
+		}
+	}
+
+	if cache.insert(&key, &data).is_err() {
+		return respond(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			"Data could not be stored",
+		);
+	}
+
+	let encoded = b64encode(&key);
+	Response::builder()
+		.status(StatusCode::CREATED)
+		.header(header::LOCATION, format!("/{}", encoded))
+		.header(header::ETAG, encoded.clone())
+		.body(Body::from(encoded))
+}
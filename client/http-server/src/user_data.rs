@@ -1,22 +1,48 @@
 use codec::Decode;
-use frame_support::storage::generator::StorageDoubleMap;
-use hyper::Uri;
+use frame_support::storage::generator::{StorageDoubleMap, StorageMap, StorageValue};
+use hyper::{header, HeaderMap, StatusCode, Uri};
+use log::warn;
 use pallet_lfs_user_data as pallet;
 use sc_client::Client;
 use sc_client_api::{backend, CallExecutor};
 use sp_core::crypto::Ss58Codec;
 use sp_core::storage::StorageKey;
+use sp_lfs_cache::{Cache, RuntimeCacheInterfaceWrapper};
 use sp_lfs_core::{LfsId, LfsReference};
-use sp_runtime::{generic::BlockId, traits::Block as BlockT};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor, SaturatedConversion, UniqueSaturatedFrom},
+};
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::traits::Resolver;
+use crate::config::ResolveMode;
+use crate::helpers::{mime_for_path, query_param};
+use crate::rules::RulesCache;
+use crate::traits::{Resolution, Resolved, ResolvedKind, Resolver};
+
+/// Name of the `UserDataResolver` in the configured routes
+pub const RESOLVER_NAME: &str = "user-data";
+/// Entry holding the redirect, rewrite and header rules of a site, see `rules::Rules`
+pub const REDIRECTS_ENTRY: &str = "_redirects";
+/// Query parameter to request the pending, not yet confirmed, version of an entry
+pub const PREVIEW_PARAM: &str = "preview";
+/// Query parameter to resolve the path in the state of the block with the given hash
+pub const AT_PARAM: &str = "at";
+/// Path prefix to resolve the rest of the path at the given block number, e.g. `/@1200/`
+pub const BLOCK_NUMBER_PREFIX: &str = "/@";
 
 #[derive(Clone, Debug)]
 enum NextResolveStep {
+	Rules,
 	UserData,
+	UserIndex,
+	UserDirectory,
+	UserNotFound,
 	RootData,
+	RootIndex,
+	RootDirectory,
 	Glob,
 	NotFound,
 	End,
@@ -25,21 +51,55 @@ enum NextResolveStep {
 impl NextResolveStep {
 	fn next(&self) -> Self {
 		match self {
-			NextResolveStep::UserData => NextResolveStep::RootData,
-			NextResolveStep::RootData => NextResolveStep::Glob,
+			NextResolveStep::Rules => NextResolveStep::UserData,
+			NextResolveStep::UserData => NextResolveStep::UserIndex,
+			NextResolveStep::UserIndex => NextResolveStep::UserDirectory,
+			NextResolveStep::UserDirectory => NextResolveStep::UserNotFound,
+			NextResolveStep::UserNotFound => NextResolveStep::RootData,
+			NextResolveStep::RootData => NextResolveStep::RootIndex,
+			NextResolveStep::RootIndex => NextResolveStep::RootDirectory,
+			NextResolveStep::RootDirectory => NextResolveStep::Glob,
 			NextResolveStep::Glob => NextResolveStep::NotFound,
 			NextResolveStep::NotFound => NextResolveStep::End,
 			NextResolveStep::End => NextResolveStep::End,
 		}
 	}
+
+	/// Name of the step, for logging
+	fn name(&self) -> &'static str {
+		match self {
+			NextResolveStep::Rules => "rules",
+			NextResolveStep::UserData => "user-data",
+			NextResolveStep::UserIndex => "user-index",
+			NextResolveStep::UserDirectory => "user-directory",
+			NextResolveStep::UserNotFound => "user-not-found",
+			NextResolveStep::RootData => "root-data",
+			NextResolveStep::RootIndex => "root-index",
+			NextResolveStep::RootDirectory => "root-directory",
+			NextResolveStep::Glob => "glob",
+			NextResolveStep::NotFound => "not-found",
+			NextResolveStep::End => "end",
+		}
+	}
 }
 
 pub struct UserDataResolveIterator<L, B, E, Block: BlockT, RA, T: pallet::Trait> {
 	client: Arc<Client<B, E, Block, RA>>,
-	best_block: BlockId<Block>,
+	at: BlockId<Block>,
+	/// The state of `at` will not change anymore
+	immutable: bool,
 	root_key: T::AccountId,
+	/// Serve the entries of any account under `/<account>/`, `false` on a registered
+	/// domain, which only serves the entries of its owner, `root_key`
+	per_account: bool,
 	_marker: PhantomData<(T, L)>,
 	uri: Uri,
+	/// The path to resolve, without any block prefix
+	path: String,
+	preview: bool,
+	rules: Option<Arc<RulesCache>>,
+	/// Headers the rules of the site add to its entries
+	headers: Vec<(String, String)>,
 	step: NextResolveStep,
 }
 
@@ -49,41 +109,183 @@ where
 	E: CallExecutor<Block>,
 	Block: BlockT,
 	L: LfsId,
-	T: pallet::Trait,
+	T: pallet::Trait + pallet_timestamp::Trait,
 {
 	fn new(
 		client: Arc<Client<B, E, Block, RA>>,
-		best_block: BlockId<Block>,
+		at: BlockId<Block>,
+		immutable: bool,
 		root_key: T::AccountId,
+		per_account: bool,
 		uri: Uri,
+		path: String,
+		rules: Option<Arc<RulesCache>>,
 	) -> Self {
+		let preview = query_param(&uri, PREVIEW_PARAM).is_some();
 		Self {
 			client,
-			best_block,
+			at,
+			immutable,
 			root_key,
+			per_account,
 			uri,
-			step: NextResolveStep::UserData,
+			path,
+			preview,
+			rules,
+			headers: Vec::new(),
+			step: NextResolveStep::Rules,
 			_marker: Default::default(),
 		}
 	}
 
-	fn lookup(&self, key: &StorageKey) -> Option<L> {
+	/// Read and decode the storage entry at `key` in the state of block `at`
+	fn read<D: Decode>(&self, at: &BlockId<Block>, key: Vec<u8>) -> Option<D> {
 		self.client
-			.storage(&self.best_block, key)
+			.storage(at, &StorageKey(key))
+			.ok()?
+			.and_then(|d| D::decode(&mut d.0.as_slice()).ok())
+	}
+
+	/// The time `entry` of `who` was last changed, taken from the
+	/// timestamp of the block the change happened in
+	fn changed_at(&self, who: &T::AccountId, entry: &[u8]) -> Option<SystemTime> {
+		let changed: T::BlockNumber = self.read(
+			&self.at,
+			pallet::UserDataChangedAt::<T>::storage_double_map_final_key(who, entry),
+		)?;
+		let number = NumberFor::<Block>::unique_saturated_from(changed.saturated_into::<u64>());
+		let moment: T::Moment = self.read(
+			&BlockId::Number(number),
+			pallet_timestamp::Now::<T>::storage_value_final_key().to_vec(),
+		)?;
+		Some(UNIX_EPOCH + Duration::from_millis(moment.saturated_into::<u64>()))
+	}
+
+	fn lookup(&self, who: &T::AccountId, entry: &str) -> Option<Resolution<L>> {
+		let entry = entry.as_bytes();
+		if self.preview {
+			let pending = self
+				.read::<LfsReference>(
+					&self.at,
+					pallet::PendingUserData::<T>::storage_double_map_final_key(who, entry),
+				)
+				.and_then(|r| L::try_from(r).ok());
+			if let Some(l) = pending {
+				return Some(Resolution::Key(Resolved {
+					expected: true,
+					preview: true,
+					..Resolved::mutable(l, None)
+				}));
+			}
+		}
+		let key = StorageKey(pallet::UserData::<T>::storage_double_map_final_key(
+			who, entry,
+		));
+		self.client
+			.storage(&self.at, &key)
 			.map(|o| {
 				o.map(|d| {
 					// user data is stored as an opaque LFS reference
 					LfsReference::decode(&mut d.0.as_slice())
 						// which we then convert into an LFSid
 						.map(|i| L::try_from(i).ok())
-						.map_err(|_| {
-							println!("UserData Entry {:?} holds a non-key: {:?}.", key, d.0)
-						})
+						.map_err(|_| warn!("UserData Entry {:?} holds a non-key: {:?}.", key, d.0))
 						.ok()?
 				})?
 			})
 			.ok()?
+			.map(|l| {
+				Resolution::Key(Resolved {
+					expected: true,
+					immutable: self.immutable,
+					..Resolved::mutable(l, self.changed_at(who, entry))
+				})
+			})
+	}
+
+	/// Look up the `index.html` of a directory-like `entry`
+	fn index(&self, who: &T::AccountId, entry: &str) -> Option<Resolution<L>> {
+		if is_directory(entry) {
+			self.lookup(who, &format!("{}index.html", entry))
+		} else {
+			None
+		}
+	}
+
+	/// Redirect to the directory, if only a directory of that name exists,
+	/// or list the entries of a directory-like `entry`
+	fn directory(&self, who: &T::AccountId, entry: &str) -> Option<Resolution<L>> {
+		let keys: Vec<pallet::EntryKey> = self.read(
+			&self.at,
+			pallet::UserDataKeys::<T>::storage_map_final_key(who),
+		)?;
+
+		if !is_directory(entry) {
+			let dir = format!("{}/", entry);
+			if !keys.iter().any(|k| k.starts_with(dir.as_bytes())) {
+				return None;
+			}
+			let location = match self.uri.query() {
+				Some(query) => format!("{}/?{}", self.uri.path(), query),
+				None => format!("{}/", self.uri.path()),
+			};
+			return Some(Resolution::Redirect(
+				StatusCode::MOVED_PERMANENTLY,
+				location,
+			));
+		}
+
+		let mut children = keys
+			.iter()
+			.filter(|k| k.starts_with(entry.as_bytes()))
+			.filter_map(|k| {
+				let rest = String::from_utf8_lossy(&k[entry.len()..]).into_owned();
+				// only direct children, sub directories are listed by name
+				let child = match rest.find('/') {
+					Some(idx) => rest[..=idx].to_owned(),
+					None => rest,
+				};
+				if child.is_empty() {
+					None
+				} else {
+					Some(child)
+				}
+			})
+			.collect::<Vec<_>>();
+		children.sort();
+		children.dedup();
+
+		if children.is_empty() {
+			None
+		} else {
+			Some(Resolution::Listing(children))
+		}
 	}
+
+	/// The `_404` page of `who`
+	fn not_found(&self, who: &T::AccountId) -> Option<Resolution<L>> {
+		match tagged(self.lookup(who, "_404"), ResolvedKind::NotFound) {
+			// a missing not-found page should not hold up the response
+			Some(Resolution::Key(resolved)) => Some(Resolution::Key(Resolved {
+				expected: false,
+				..resolved
+			})),
+			other => other,
+		}
+	}
+}
+
+/// Mark the key of `resolution` as being of `kind`
+fn tagged<L>(resolution: Option<Resolution<L>>, kind: ResolvedKind) -> Option<Resolution<L>> {
+	resolution.map(|r| match r {
+		Resolution::Key(resolved) => Resolution::Key(Resolved { kind, ..resolved }),
+		other => other,
+	})
+}
+
+/// Entries that are empty or end with a `/` are treated as directories
+fn is_directory(entry: &str) -> bool {
+	entry.is_empty() || entry.ends_with('/')
 }
 
 impl<L, B, E, Block, RA, T> core::iter::Iterator for UserDataResolveIterator<L, B, E, Block, RA, T>
@@ -92,64 +294,179 @@ where
 	E: CallExecutor<Block>,
 	Block: BlockT,
 	L: LfsId,
-	T: pallet::Trait,
+	T: pallet::Trait + pallet_timestamp::Trait,
 	T::AccountId: Ss58Codec,
 {
-	type Item = L;
+	type Item = Resolution<L>;
 
 	fn next(&mut self) -> Option<Self::Item> {
 		loop {
-			let key = match self.step {
-				NextResolveStep::UserData => {
-					let mut splitter = self.uri.path().splitn(3, "/").filter(|s| s.len() > 0);
-					let user_key = splitter.next();
-					user_key
-						.and_then(|mut u| T::AccountId::from_string(&mut u).ok())
-						.map(|key| {
-							pallet::UserData::<T>::storage_double_map_final_key(
-								&key,
-								// the rest is the key we want to look up
-								// fallback is to check for `""`
-								splitter.next().unwrap_or("").as_bytes().to_vec(),
-							)
-						})
+			let step = self.step.clone();
+			self.step = self.step.next();
+
+			let mut resolution = match step {
+				NextResolveStep::Rules => self.apply_rules(),
+				NextResolveStep::UserData => self
+					.user_entry()
+					.and_then(|(who, entry)| self.lookup(&who, &entry)),
+				NextResolveStep::UserIndex => self
+					.user_entry()
+					.and_then(|(who, entry)| self.index(&who, &entry)),
+				NextResolveStep::UserDirectory => self
+					.user_entry()
+					.and_then(|(who, entry)| self.directory(&who, &entry)),
+				NextResolveStep::UserNotFound => {
+					let page = self.user_entry().and_then(|(who, _)| self.not_found(&who));
+					if page.is_some() {
+						// the user's own not-found page takes precedence over the root's site
+						self.step = NextResolveStep::End;
+					}
+					page
 				}
-				NextResolveStep::RootData => {
-					let path = self.uri.path().split_at(1).1;
-					Some(pallet::UserData::<T>::storage_double_map_final_key(
-						&self.root_key,
-						path.as_bytes(), // drop leading `/`
-					))
-				}
-				NextResolveStep::Glob => Some(pallet::UserData::<T>::storage_double_map_final_key(
-					&self.root_key,
-					b".*".to_vec(),
-				)),
-				NextResolveStep::NotFound => {
-					Some(pallet::UserData::<T>::storage_double_map_final_key(
-						&self.root_key,
-						b"_404".to_vec(),
-					))
+				NextResolveStep::RootData => self.lookup(&self.root_key, self.root_entry()),
+				NextResolveStep::RootIndex => self.index(&self.root_key, self.root_entry()),
+				NextResolveStep::RootDirectory => self.directory(&self.root_key, self.root_entry()),
+				NextResolveStep::Glob => {
+					tagged(self.lookup(&self.root_key, ".*"), ResolvedKind::Fallback)
 				}
+				NextResolveStep::NotFound => self.not_found(&self.root_key),
 				NextResolveStep::End => {
 					// we are done.
-					break;
+					return None;
 				}
 			};
 
-			self.step = self.step.next();
-			if let Some(l) = key.and_then(|k| self.lookup(&StorageKey(k))) {
-				return Some(l);
+			if let Some(Resolution::Key(ref mut resolved)) = resolution {
+				resolved.step = Some(step.name());
+				if resolved.kind == ResolvedKind::Exact {
+					resolved.headers.extend(self.headers.iter().cloned());
+				}
 			}
+			if resolution.is_some() {
+				return resolution;
+			}
+		}
+	}
+}
+
+impl<L, B, E, Block, RA, T> UserDataResolveIterator<L, B, E, Block, RA, T>
+where
+	B: backend::Backend<Block>,
+	E: CallExecutor<Block>,
+	Block: BlockT,
+	L: LfsId,
+	T: pallet::Trait + pallet_timestamp::Trait,
+	T::AccountId: Ss58Codec,
+{
+	/// The account the path starts with and the entry of that account
+	/// it refers to, fallback is to check for `""`
+	fn user_entry(&self) -> Option<(T::AccountId, String)> {
+		if !self.per_account {
+			return None;
 		}
+		let mut splitter = self.path.splitn(3, "/").filter(|s| s.len() > 0);
+		let user_key = splitter.next();
+		user_key
+			.and_then(|mut u| T::AccountId::from_string(&mut u).ok())
+			.map(|key| (key, splitter.next().unwrap_or("").to_owned()))
+	}
 
+	/// The entry of the root account the path refers to
+	fn root_entry(&self) -> &str {
+		// drop leading `/`
+		self.path.split_at(1).1
+	}
+
+	/// Apply the `_redirects` rules of the site the path belongs to: redirect right
+	/// away or remember the rewritten path and the headers for the following steps
+	fn apply_rules(&mut self) -> Option<Resolution<L>> {
+		let cache = self.rules.clone()?;
+		let (who, base, entry) = match self.user_entry() {
+			Some((who, entry)) => {
+				let base = self.path[..self.path.len() - entry.len()]
+					.trim_end_matches('/')
+					.to_owned();
+				(who, base, entry)
+			}
+			None => (
+				self.root_key.clone(),
+				String::new(),
+				self.root_entry().to_owned(),
+			),
+		};
+		let reference: LfsReference = self.read(
+			&self.at,
+			pallet::UserData::<T>::storage_double_map_final_key(&who, REDIRECTS_ENTRY.as_bytes()),
+		)?;
+		let applied = cache.get(&reference)?.apply(&format!("/{}", entry));
+		self.headers = applied.headers;
+
+		if let Some((status, target)) = applied.redirect {
+			let location = if target.starts_with('/') {
+				// stay at the block the request was pinned to, if any
+				match block_number_prefix(self.uri.path()) {
+					Some((number, _)) => {
+						format!("{}{}{}{}", BLOCK_NUMBER_PREFIX, number, base, target)
+					}
+					None => format!("{}{}", base, target),
+				}
+			} else {
+				target
+			};
+			return Some(Resolution::Redirect(status, location));
+		}
+		if let Some(target) = applied.rewrite {
+			let target = if target.starts_with('/') {
+				target
+			} else {
+				format!("/{}", target)
+			};
+			if let Some(mime) = mime_for_path(&target) {
+				// the type of what is served, rather than of what was requested
+				self.headers.insert(
+					0,
+					(header::CONTENT_TYPE.as_str().to_owned(), mime.to_owned()),
+				);
+			}
+			self.path = format!("{}{}", base, target);
+		}
 		None
 	}
 }
 
+/// Split a `/@<number>/rest` path into the block number and `/rest`
+fn block_number_prefix(path: &str) -> Option<(u64, String)> {
+	if !path.starts_with(BLOCK_NUMBER_PREFIX) {
+		return None;
+	}
+	let rest = &path[BLOCK_NUMBER_PREFIX.len()..];
+	let (number, rest) = match rest.find('/') {
+		Some(idx) => rest.split_at(idx),
+		None => (rest, "/"),
+	};
+	number.parse().ok().map(|n| (n, rest.to_owned()))
+}
+
+/// The lowercase host name the request was sent to, without the port
+fn host_of(uri: &Uri, headers: &HeaderMap) -> Option<String> {
+	let host = match headers.get(header::HOST) {
+		Some(h) => h.to_str().ok()?,
+		None => uri.host()?,
+	};
+	let host = match host.rfind(':') {
+		// not an IPv6 address without port, e.g. `[::1]`
+		Some(idx) if !host[idx..].contains(']') => &host[..idx],
+		_ => host,
+	};
+	Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
+
 /// Resolve uri via on-chain UserData
 pub struct UserDataResolver<B, E, Block: BlockT, RA, T> {
 	client: Arc<Client<B, E, Block, RA>>,
+	mode: ResolveMode,
+	unknown_host_fallback: bool,
+	rules: Option<Arc<RulesCache>>,
 	_marker: PhantomData<T>,
 }
 
@@ -163,6 +480,9 @@ where
 	fn clone(&self) -> Self {
 		Self {
 			client: self.client.clone(),
+			mode: self.mode,
+			unknown_host_fallback: self.unknown_host_fallback,
+			rules: self.rules.clone(),
 			_marker: Default::default(),
 		}
 	}
@@ -178,9 +498,77 @@ where
 	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
 		UserDataResolver {
 			client,
+			mode: ResolveMode::Best,
+			unknown_host_fallback: true,
+			rules: None,
 			_marker: Default::default(),
 		}
 	}
+
+	/// Resolve paths at the block given by `mode`, unless the request pins one
+	pub fn with_mode(self, mode: ResolveMode) -> Self {
+		UserDataResolver { mode, ..self }
+	}
+
+	/// Whether to serve the root account's entries for hosts that aren't registered
+	/// on chain, rather than nothing at all
+	pub fn with_unknown_host_fallback(self, unknown_host_fallback: bool) -> Self {
+		UserDataResolver {
+			unknown_host_fallback,
+			..self
+		}
+	}
+
+	/// Apply the `_redirects` rules of sites, reading them from `cache`
+	pub fn with_rules<C, L>(self, cache: C) -> Self
+	where
+		C: Cache<L> + 'static,
+		L: LfsId + 'static,
+	{
+		let cache = RuntimeCacheInterfaceWrapper::from(cache);
+		UserDataResolver {
+			rules: Some(Arc::new(RulesCache::new(Box::new(cache)))),
+			..self
+		}
+	}
+
+	/// The account registered for `domain` in the state of block `at`
+	fn domain_owner(&self, at: &BlockId<Block>, domain: &str) -> Option<T::AccountId> {
+		self.client
+			.storage(
+				at,
+				&StorageKey(pallet::Domains::<T>::storage_map_final_key(
+					domain.as_bytes(),
+				)),
+			)
+			.ok()?
+			.and_then(|d| T::AccountId::decode(&mut d.0.as_slice()).ok())
+	}
+
+	/// The block to resolve `uri` at, whether its state is final and the
+	/// path to resolve. `None` if the block requested isn't valid.
+	fn block_for(&self, uri: &Uri) -> Option<(BlockId<Block>, bool, String)> {
+		let info = self.client.chain_info();
+		let finalized: u64 = info.finalized_number.saturated_into();
+
+		if let Some((number, path)) = block_number_prefix(uri.path()) {
+			let at = BlockId::Number(NumberFor::<Block>::unique_saturated_from(number));
+			// until finalized, the block at that height may still change
+			return Some((at, number <= finalized, path));
+		}
+
+		let path = uri.path().to_owned();
+		if let Some(hash) = query_param(uri, AT_PARAM) {
+			let raw = hex::decode(hash.trim_start_matches("0x")).ok()?;
+			let hash = Block::Hash::decode(&mut raw.as_slice()).ok()?;
+			return Some((BlockId::Hash(hash), true, path));
+		}
+
+		Some(match self.mode {
+			ResolveMode::Best => (BlockId::Hash(info.best_hash), false, path),
+			ResolveMode::Finalized => (BlockId::Hash(info.finalized_hash), false, path),
+		})
+	}
 }
 
 impl<B, E, Block, RA, T, L> Resolver<L> for UserDataResolver<B, E, Block, RA, T>
@@ -188,20 +576,88 @@ where
 	B: backend::Backend<Block>,
 	E: CallExecutor<Block>,
 	Block: BlockT,
-	T: pallet::Trait,
+	T: pallet::Trait + pallet_timestamp::Trait,
 	L: LfsId,
 	T::AccountId: Ss58Codec,
 {
 	/// The iterator this resolves to, must yield `LfsdId`s
 	type Iterator = Box<UserDataResolveIterator<L, B, E, Block, RA, T>>;
 
-	/// Given the uri, yield the `LfsId`s to look up
-	fn resolve(&self, uri: Uri) -> Option<Self::Iterator> {
+	/// Given the uri and the host it was requested from, yield the `LfsId`s to look up
+	fn resolve(&self, uri: Uri, headers: &HeaderMap) -> Option<Self::Iterator> {
+		let (at, immutable, path) = self.block_for(&uri)?;
+		// the account registered for the host takes the place of the root account,
+		// without serving other accounts' entries on the owner's origin
+		let (root_key, per_account) =
+			match host_of(&uri, headers).and_then(|h| self.domain_owner(&at, &h)) {
+				Some(owner) => (owner, false),
+				None if self.unknown_host_fallback => (T::AccountId::default(), true),
+				None => return None,
+			};
 		Some(Box::new(UserDataResolveIterator::new(
 			self.client.clone(),
-			BlockId::Hash(self.client.chain_info().best_hash),
-			T::AccountId::default(),
+			at,
+			immutable,
+			root_key,
+			per_account,
 			uri,
+			path,
+			self.rules.clone(),
 		)))
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn host(value: &str) -> Option<String> {
+		let mut headers = HeaderMap::new();
+		headers.insert(header::HOST, value.parse().unwrap());
+		host_of(&"/".parse().unwrap(), &headers)
+	}
+
+	#[test]
+	fn parses_block_number_prefixes() {
+		assert_eq!(
+			block_number_prefix("/@1200/index.html"),
+			Some((1200, "/index.html".into()))
+		);
+		assert_eq!(block_number_prefix("/@1200/"), Some((1200, "/".into())));
+		assert_eq!(block_number_prefix("/@1200"), Some((1200, "/".into())));
+	}
+
+	#[test]
+	fn ignores_paths_without_block_number() {
+		assert_eq!(block_number_prefix("/index.html"), None);
+		assert_eq!(block_number_prefix("/@latest/index.html"), None);
+		assert_eq!(block_number_prefix("/@/index.html"), None);
+		assert_eq!(block_number_prefix("/a/@1200/index.html"), None);
+	}
+
+	#[test]
+	fn strips_the_port_of_hosts() {
+		assert_eq!(host("example.com"), Some("example.com".into()));
+		assert_eq!(host("example.com:8080"), Some("example.com".into()));
+		assert_eq!(host("127.0.0.1:8080"), Some("127.0.0.1".into()));
+	}
+
+	#[test]
+	fn keeps_ipv6_addresses() {
+		assert_eq!(host("[::1]:8080"), Some("[::1]".into()));
+		assert_eq!(host("[::1]"), Some("[::1]".into()));
+	}
+
+	#[test]
+	fn normalizes_hosts() {
+		assert_eq!(host("Example.COM"), Some("example.com".into()));
+		assert_eq!(host("example.com."), Some("example.com".into()));
+	}
+
+	#[test]
+	fn falls_back_to_the_uri_host() {
+		let uri = "http://Example.com:8080/".parse().unwrap();
+		assert_eq!(host_of(&uri, &HeaderMap::new()), Some("example.com".into()));
+		assert_eq!(host_of(&"/".parse().unwrap(), &HeaderMap::new()), None);
+	}
+}
@@ -1,6 +1,7 @@
 use sp_lfs_cache::Cache;
 use sp_lfs_core::LfsId;
 use std::fs;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::PathBuf;
 
 /// a super simplistic disk cache
@@ -40,6 +41,20 @@ where
 		fs::read(path).map_err(|_| ())
 	}
 
+	fn size(&self, key: &Key) -> Result<u64, ()> {
+		let path = self.make_local_path(key);
+		fs::metadata(path).map(|m| m.len()).map_err(|_| ())
+	}
+
+	fn read_range(&self, key: &Key, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		let path = self.make_local_path(key);
+		let mut file = fs::File::open(path).map_err(|_| ())?;
+		file.seek(SeekFrom::Start(offset)).map_err(|_| ())?;
+		let mut data = Vec::new();
+		file.take(len).read_to_end(&mut data).map_err(|_| ())?;
+		Ok(data)
+	}
+
 	fn insert(&self, key: &Key, data: &Vec<u8>) -> Result<(), ()> {
 		let path = self.make_local_path(key);
 		fs::write(path, data).map_err(|_| ())
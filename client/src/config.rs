@@ -1,9 +1,13 @@
+use sc_lfs_config::ServerConfig;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 use toml;
 
 const DEFAULT_MEM_LIMIT: usize = 1024;
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_SESSION_TIMEOUT: u64 = 600;
+const DEFAULT_MAX_SESSIONS: usize = 64;
 
 /// Configuration for the LFS cache
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,10 +18,33 @@ pub struct CacheConfig {
 	pub(crate) mem_limit: usize,
 }
 
+/// Configuration of the LFS RPC
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RpcConfig {
+	/// Maximum size of data uploaded via `lfs_upload` or an upload session in bytes,
+	/// `0` for no limit
+	pub max_upload_size: u64,
+	/// Uploads per second accepted on average, `0` for no limit
+	pub upload_rate: f64,
+	/// Uploads accepted at once, beyond the average rate
+	pub upload_burst: u32,
+	/// Seconds after which an upload session not receiving chunks is dropped
+	pub session_timeout: u64,
+	/// Upload sessions open at once, `0` for no limit
+	pub max_sessions: usize,
+}
+
 /// Our lfs configuration file
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LfsConfig {
 	pub cache: CacheConfig,
+	/// The RPC
+	#[serde(default)]
+	pub rpc: RpcConfig,
+	/// The HTTP server
+	#[serde(default)]
+	pub http: ServerConfig,
 }
 
 impl core::default::Default for CacheConfig {
@@ -29,10 +56,24 @@ impl core::default::Default for CacheConfig {
 	}
 }
 
+impl core::default::Default for RpcConfig {
+	fn default() -> RpcConfig {
+		RpcConfig {
+			max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+			upload_rate: 0.0,
+			upload_burst: 0,
+			session_timeout: DEFAULT_SESSION_TIMEOUT,
+			max_sessions: DEFAULT_MAX_SESSIONS,
+		}
+	}
+}
+
 impl std::default::Default for LfsConfig {
 	fn default() -> LfsConfig {
 		LfsConfig {
 			cache: Default::default(),
+			rpc: Default::default(),
+			http: Default::default(),
 		}
 	}
 }
@@ -59,3 +100,15 @@ pub fn load_config(config_file_path: &Path) -> Result<LfsConfig, String> {
 	toml::from_slice::<LfsConfig>(&content)
 		.map_err(|e| format!("Error parsing LFS configuration : {}", e))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn default_config_round_trips() {
+		let written = toml::to_string(&LfsConfig::default()).expect("defaults serialize");
+		let read: LfsConfig = toml::from_str(&written).expect("defaults deserialize");
+		assert_eq!(toml::to_string(&read).unwrap(), written);
+	}
+}
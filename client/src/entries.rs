@@ -0,0 +1,113 @@
+use codec::Codec;
+use futures::{future, stream, Future, StreamExt};
+use sc_client_api::BlockchainEvents;
+use sc_lfs_config::ResolveMode;
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_lfs_runtime_api::{EntryState as RuntimeEntryState, LfsApi as LfsRuntimeApi};
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor, SaturatedConversion},
+};
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::lfs_id::{LfsId, LfsReference};
+use crate::rpc::{EntryState, EntryStateProvider, EntrySubscriptions};
+
+/// Queries the state of entries via the `LfsApi` runtime API
+pub struct RuntimeEntries<C, Block, AccountId> {
+	client: Arc<C>,
+	_marker: PhantomData<(Block, AccountId)>,
+}
+
+impl<C, Block, AccountId> RuntimeEntries<C, Block, AccountId> {
+	pub fn new(client: Arc<C>) -> Self {
+		RuntimeEntries {
+			client,
+			_marker: Default::default(),
+		}
+	}
+}
+
+impl<C, Block, AccountId> RuntimeEntries<C, Block, AccountId>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi + HeaderBackend<Block> + BlockchainEvents<Block> + Send + Sync,
+	C::Api: LfsRuntimeApi<Block, AccountId, NumberFor<Block>>,
+	AccountId: Codec + Send + Sync,
+{
+	/// The best or the last finalized block, as given by `at`
+	fn block(&self, at: ResolveMode) -> BlockId<Block> {
+		let info = self.client.info();
+		BlockId::Hash(match at {
+			ResolveMode::Best => info.best_hash,
+			ResolveMode::Finalized => info.finalized_hash,
+		})
+	}
+
+	/// Update the `subscriptions` whenever a new best block is imported or a block is finalized
+	pub fn watch(self, subscriptions: Arc<EntrySubscriptions>) -> impl Future<Output = ()> {
+		let best = self
+			.client
+			.import_notification_stream()
+			.filter(|n| future::ready(n.is_new_best))
+			.map(|_| ResolveMode::Best);
+		let finalized = self
+			.client
+			.finality_notification_stream()
+			.map(|_| ResolveMode::Finalized);
+
+		let entries = Arc::new(self);
+		stream::select(best, finalized).for_each(move |at| {
+			let subscriptions = subscriptions.clone();
+			let entries = entries.clone();
+			async move { subscriptions.update(&*entries, at).await }
+		})
+	}
+}
+
+impl<C, Block, AccountId> EntryStateProvider<LfsId> for RuntimeEntries<C, Block, AccountId>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi + HeaderBackend<Block> + BlockchainEvents<Block> + Send + Sync,
+	C::Api: LfsRuntimeApi<Block, AccountId, NumberFor<Block>>,
+	AccountId: Codec + Send + Sync,
+{
+	fn entry_state(&self, id: &LfsId, at: ResolveMode) -> Option<EntryState> {
+		let reference: LfsReference = id.clone().into();
+		let state = self
+			.client
+			.runtime_api()
+			.entry_state(&self.block(at), reference)
+			.ok()??;
+		Some(match state {
+			RuntimeEntryState::Pending { since, listeners } => EntryState::Pending {
+				since: since.saturated_into(),
+				listeners,
+			},
+			RuntimeEntryState::Resolved {
+				since,
+				latest,
+				ref_count,
+				size,
+			} => EntryState::Resolved {
+				since: since.saturated_into(),
+				latest: latest.saturated_into(),
+				ref_count,
+				size,
+			},
+		})
+	}
+
+	fn pending(&self, offset: u32, limit: u32) -> Vec<LfsId> {
+		self.client
+			.runtime_api()
+			.pending(&self.block(ResolveMode::Best), offset, limit)
+			.unwrap_or_default()
+			.into_iter()
+			.filter_map(|reference| LfsId::try_from(reference).ok())
+			.collect()
+	}
+}
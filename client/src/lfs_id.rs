@@ -10,6 +10,9 @@ use blake3;
 
 type Hash256 = [u8; 32];
 
+/// Longest data stored as `LfsId::Raw`, longer data is hashed
+pub const MAX_RAW_LENGTH: usize = 32;
+
 #[derive(Debug, Encode, Decode, Clone, Hash, Eq)]
 /// Our Large File System ID
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
@@ -30,6 +33,20 @@ pub enum LfsId {
 	Sha3(Hash256),
 }
 
+/// The ways to compute an `LfsId` of some data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "lowercase"))]
+pub enum HashAlgorithm {
+	/// Not hashed, the id holds the data itself
+	Raw,
+	Blake2,
+	#[cfg(feature = "with-blake3")]
+	Blake3,
+	Sha2,
+	Sha3,
+}
+
 impl LfsId {
 	#[cfg(feature = "with-blake3")]
 	pub fn default(data: &Vec<u8>) -> Self {
@@ -52,6 +69,32 @@ impl LfsId {
 	pub fn blake3(data: &Vec<u8>) -> Self {
 		LfsId::Blake3(*blake3::hash(data).as_bytes())
 	}
+	/// The id of `data` computed with `algorithm`
+	pub fn with_algorithm(algorithm: HashAlgorithm, data: &Vec<u8>) -> Self {
+		match algorithm {
+			HashAlgorithm::Raw => LfsId::Raw(data.clone()),
+			HashAlgorithm::Blake2 => Self::blake2(data),
+			#[cfg(feature = "with-blake3")]
+			HashAlgorithm::Blake3 => Self::blake3(data),
+			HashAlgorithm::Sha2 => Self::sha2(data),
+			HashAlgorithm::Sha3 => Self::sha3(data),
+		}
+	}
+	/// The algorithm this id was computed with
+	pub fn algorithm(&self) -> HashAlgorithm {
+		match self {
+			LfsId::Raw(_) => HashAlgorithm::Raw,
+			LfsId::Blake2(_) => HashAlgorithm::Blake2,
+			#[cfg(feature = "with-blake3")]
+			LfsId::Blake3(_) => HashAlgorithm::Blake3,
+			LfsId::Sha2(_) => HashAlgorithm::Sha2,
+			LfsId::Sha3(_) => HashAlgorithm::Sha3,
+		}
+	}
+	/// Whether this is the id of `data`, computed with the algorithm of this id
+	pub fn verify(&self, data: &Vec<u8>) -> bool {
+		&Self::with_algorithm(self.algorithm(), data) == self
+	}
 }
 
 impl std::fmt::Display for LfsId {
@@ -94,7 +137,7 @@ impl core::convert::Into<LfsReference> for LfsId {
 
 impl LfsIdT for LfsId {
 	fn for_data(data: &Vec<u8>) -> Result<Self, ()> {
-		if data.len() <= 32 {
+		if data.len() <= MAX_RAW_LENGTH {
 			Ok(LfsId::Raw(data.clone()))
 		} else {
 			Ok(Self::default(data))
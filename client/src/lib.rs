@@ -10,9 +10,15 @@ pub mod config;
 #[cfg(feature = "jsonrpc")]
 pub mod rpc;
 
+#[cfg(all(feature = "jsonrpc", feature = "entries"))]
+pub mod entries;
+
 #[cfg(feature = "std")]
 pub struct DefaultClient {
 	cache: cache::ClientCache,
+	rpc: config::RpcConfig,
+	#[cfg(feature = "jsonrpc")]
+	entry_subscriptions: std::sync::Arc<rpc::EntrySubscriptions>,
 }
 
 impl DefaultClient {
@@ -47,12 +53,27 @@ impl DefaultClient {
 	) -> Result<Self, String> {
 		Ok(DefaultClient {
 			cache: cache::from_config(cfg, converter)?,
+			rpc: cfg.rpc.clone(),
+			#[cfg(feature = "jsonrpc")]
+			entry_subscriptions: Default::default(),
 		})
 	}
 
 	#[cfg(feature = "jsonrpc")]
 	pub fn make_rpc(&self) -> rpc::LfsRpc<cache::ClientCache> {
 		rpc::LfsRpc::new(self.cache.clone())
+			.with_max_upload_size(self.rpc.max_upload_size)
+			.with_upload_rate(self.rpc.upload_rate, self.rpc.upload_burst)
+			.with_session_timeout(std::time::Duration::from_secs(self.rpc.session_timeout))
+			.with_max_sessions(self.rpc.max_sessions)
+			.with_entry_subscriptions(self.entry_subscriptions.clone())
+	}
+
+	/// The `lfs_subscribeEntry` subscriptions of the RPCs made, to be updated
+	/// by a task watching the chain
+	#[cfg(feature = "jsonrpc")]
+	pub fn entry_subscriptions(&self) -> std::sync::Arc<rpc::EntrySubscriptions> {
+		self.entry_subscriptions.clone()
 	}
 
 	pub fn make_externalities_extension_factory(&self) -> Box<LfsExtensionsFactory> {
@@ -1,38 +1,506 @@
-use jsonrpc_core::types::error::Error as ApiError;
+use futures::compat::Future01CompatExt;
+use jsonrpc_core::types::error::{Error as ApiError, ErrorCode};
 use jsonrpc_core::Result;
 use jsonrpc_derive::rpc;
+use jsonrpc_pubsub::{typed::Sink, typed::Subscriber, PubSubMetadata, Session, SubscriptionId};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sp_core::Bytes;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::lfs_id::LfsId;
+use crate::lfs_id::{HashAlgorithm, LfsId, MAX_RAW_LENGTH};
+use sc_lfs_config::{Bucket, ResolveMode};
 use sp_lfs_cache::Cache;
 
+/// The uploaded data exceeds the maximum upload size
+const UPLOAD_TOO_LARGE: i64 = 1;
+/// Too many uploads, retry later
+const RATE_LIMITED: i64 = 2;
+/// No upload session of that id, it may have expired
+const UNKNOWN_SESSION: i64 = 3;
+/// A chunk doesn't continue the data received so far, `data` holds the bytes received
+const INVALID_OFFSET: i64 = 4;
+/// The upload can't be finished before all data was received, `data` holds the bytes received
+const INCOMPLETE_UPLOAD: i64 = 5;
+/// The uploaded data doesn't match the expected id
+const HASH_MISMATCH: i64 = 6;
+/// Too many upload sessions open, retry later
+const TOO_MANY_SESSIONS: i64 = 7;
+/// The data is too long to be stored under a raw id, use a hash algorithm instead
+const RAW_TOO_LARGE: i64 = 8;
+/// Too many entries subscribed to via this connection
+const TOO_MANY_SUBSCRIPTIONS: i64 = 9;
+
+/// How long an upload session is kept without receiving chunks
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(600);
+/// How many upload sessions may be open at once
+const DEFAULT_MAX_SESSIONS: usize = 64;
+/// Most pending entries returned by `lfs_pending` at once
+const MAX_PENDING_PAGE: u32 = 1_000;
+/// Most entries a connection may subscribe to at once
+const MAX_SUBSCRIPTIONS_PER_CONNECTION: usize = 100;
+
+fn error(code: i64, message: String) -> ApiError {
+	ApiError {
+		code: ErrorCode::ServerError(code),
+		message,
+		data: None,
+	}
+}
+
+/// An upload started by `lfs_beginUpload`, receiving its data in chunks
+struct UploadSession {
+	size: u64,
+	expected: Option<LfsId>,
+	data: Vec<u8>,
+	/// When the last chunk arrived
+	touched: Instant,
+}
+
+/// The state of an entry on chain
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "state")]
+pub enum EntryState {
+	/// Queried, but not yet confirmed to exist
+	#[serde(rename_all = "camelCase")]
+	Pending {
+		/// The block number it was first queried at
+		since: u64,
+		/// How many wait for it to be resolved
+		listeners: u32,
+	},
+	/// Confirmed to exist
+	#[serde(rename_all = "camelCase")]
+	Resolved {
+		/// The block number it was first confirmed at
+		since: u64,
+		/// The block number it was last confirmed at
+		latest: u64,
+		/// How many on chain still refer to it
+		ref_count: u32,
+		/// The size of the data in bytes, as measured when it was confirmed
+		size: u64,
+	},
+}
+
+/// What the node knows about an entry, as returned by `lfs_stat`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryStat {
+	/// Size of the data in bytes, `None` if it isn't stored on this node
+	pub size: Option<u64>,
+	/// Whether the entry is resolved on chain and still referred to,
+	/// so the data has to be kept around
+	pub pinned: bool,
+	/// The state on chain, `None` if the chain doesn't know about it
+	pub state: Option<EntryState>,
+}
+
+/// Looks up the on chain state of entries
+pub trait EntryStateProvider<Key>: Send + Sync {
+	/// The state of `id` at the best or the last finalized block, as given by `at`,
+	/// `None` if there is no such entry
+	fn entry_state(&self, id: &Key, at: ResolveMode) -> Option<EntryState>;
+
+	/// Up to `limit` of the entries pending at the best block, skipping the first `offset`
+	fn pending(&self, _offset: u32, _limit: u32) -> Vec<Key> {
+		Vec::new()
+	}
+}
+
+/// No access to the chain, no entry is known
+impl<Key> EntryStateProvider<Key> for () {
+	fn entry_state(&self, _id: &Key, _at: ResolveMode) -> Option<EntryState> {
+		None
+	}
+}
+
+/// The on chain state of a subscribed entry changed, as streamed by `lfs_subscribeEntry`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct EntryChange {
+	/// Whether this is the state at the last finalized rather than the best block
+	pub finalized: bool,
+	/// The new state, `None` once the entry was dropped or if the chain doesn't know it
+	pub state: Option<EntryState>,
+}
+
+/// A subscription to the state of an entry
+struct EntrySubscription {
+	id: LfsId,
+	sink: Sink<EntryChange>,
+	/// The connection subscribing, identified by the address of its session
+	session: usize,
+	/// The states last sent, `None` until the first one was
+	best: Option<Option<EntryState>>,
+	finalized: Option<Option<EntryState>>,
+}
+
+/// The open `lfs_subscribeEntry` subscriptions, updated by a task watching the chain
+#[derive(Default)]
+pub struct EntrySubscriptions {
+	next_id: AtomicU64,
+	subscriptions: Mutex<HashMap<u64, EntrySubscription>>,
+}
+
+impl EntrySubscriptions {
+	fn add(
+		this: &Arc<Self>,
+		session: Arc<Session>,
+		id: LfsId,
+		subscriber: Subscriber<EntryChange>,
+	) {
+		let session_id = &*session as *const Session as usize;
+		let mut subscriptions = this.subscriptions.lock();
+		let open = subscriptions
+			.values()
+			.filter(|s| s.session == session_id)
+			.count();
+		if open >= MAX_SUBSCRIPTIONS_PER_CONNECTION {
+			let _ = subscriber.reject(error(
+				TOO_MANY_SUBSCRIPTIONS,
+				format!(
+					"At most {} entries can be subscribed to per connection",
+					MAX_SUBSCRIPTIONS_PER_CONNECTION
+				),
+			));
+			return;
+		}
+		let subscription_id = this.next_id.fetch_add(1, Ordering::SeqCst);
+		if let Ok(sink) = subscriber.assign_id(SubscriptionId::Number(subscription_id)) {
+			if open == 0 {
+				// drop the subscriptions of the connection once it is closed
+				let this = Arc::downgrade(this);
+				session.on_drop(move || {
+					if let Some(this) = this.upgrade() {
+						this.subscriptions
+							.lock()
+							.retain(|_, s| s.session != session_id);
+					}
+				});
+			}
+			subscriptions.insert(
+				subscription_id,
+				EntrySubscription {
+					id,
+					sink,
+					session: session_id,
+					best: None,
+					finalized: None,
+				},
+			);
+		}
+	}
+
+	fn remove(&self, id: &SubscriptionId) -> bool {
+		match id {
+			SubscriptionId::Number(id) => self.subscriptions.lock().remove(id).is_some(),
+			_ => false,
+		}
+	}
+
+	/// Look up the state of all subscribed entries at the block given by `at`
+	/// and notify the subscribers of the ones that changed
+	pub async fn update<P: EntryStateProvider<LfsId>>(&self, entries: &P, at: ResolveMode) {
+		let finalized = at == ResolveMode::Finalized;
+		let changes = {
+			let mut subscriptions = self.subscriptions.lock();
+			subscriptions
+				.iter_mut()
+				.filter_map(|(subscription_id, subscription)| {
+					let state = entries.entry_state(&subscription.id, at);
+					let last = if finalized {
+						&mut subscription.finalized
+					} else {
+						&mut subscription.best
+					};
+					if last.as_ref() == Some(&state) {
+						return None;
+					}
+					*last = Some(state.clone());
+					Some((
+						*subscription_id,
+						subscription.sink.clone(),
+						EntryChange { finalized, state },
+					))
+				})
+				.collect::<Vec<_>>()
+		};
+
+		for (subscription_id, sink, change) in changes {
+			if sink.notify(Ok(change)).compat().await.is_err() {
+				// the subscriber is gone
+				self.subscriptions.lock().remove(&subscription_id);
+			}
+		}
+	}
+}
+
 pub use self::gen_client::Client as LfsClient;
+pub use sc_rpc_api::Metadata;
 
 /// Substrate LFS RPC API
 #[rpc]
 pub trait LfsApi<Key> {
+	/// RPC Metadata
+	type Metadata;
+
 	#[rpc(name = "lfs_get")]
 	fn get(&self, id: Key) -> Result<Vec<u8>>;
 
 	#[rpc(name = "lfs_upload")]
 	fn upload(&self, data: Vec<u8>) -> Result<Key>;
+
+	/// Like `lfs_get`, with the data hex encoded rather than as an array of numbers
+	#[rpc(name = "lfs_getBytes")]
+	fn get_bytes(&self, id: Key) -> Result<Bytes>;
+
+	/// Like `lfs_upload`, taking the data hex encoded rather than as an array of numbers
+	#[rpc(name = "lfs_uploadBytes")]
+	fn upload_bytes(&self, data: Bytes) -> Result<Key>;
+
+	/// Store `data` under its id computed with `algorithm` rather than the default one
+	#[rpc(name = "lfs_uploadAs")]
+	fn upload_as(&self, data: Bytes, algorithm: HashAlgorithm) -> Result<Key>;
+
+	/// Store `data` under `id`, failing if that isn't the id of `data`
+	#[rpc(name = "lfs_uploadExpecting")]
+	fn upload_expecting(&self, data: Bytes, id: Key) -> Result<Key>;
+
+	/// Whether the data of each of `ids` is stored on this node
+	#[rpc(name = "lfs_exists")]
+	fn exists(&self, ids: Vec<Key>) -> Result<Vec<bool>>;
+
+	/// Size, on chain state and whether the node has to keep the data of `id`
+	#[rpc(name = "lfs_stat")]
+	fn stat(&self, id: Key) -> Result<EntryStat>;
+
+	/// Up to `limit` of the entries pending on chain, skipping the first `offset`,
+	/// in the order they were queried
+	#[rpc(name = "lfs_pending")]
+	fn pending(&self, offset: u32, limit: u32) -> Result<Vec<Key>>;
+
+	/// The data of each of `ids`, `None` for the ones not stored on this node
+	#[rpc(name = "lfs_getMany")]
+	fn get_many(&self, ids: Vec<Key>) -> Result<Vec<Option<Bytes>>>;
+
+	/// Store each of `data`, returning their ids in the same order
+	#[rpc(name = "lfs_uploadMany")]
+	fn upload_many(&self, data: Vec<Bytes>) -> Result<Vec<Key>>;
+
+	/// Stream the changes of the on chain state of `id`, at the best and the
+	/// finalized block, starting with the current state once the next block is imported
+	#[pubsub(subscription = "lfs_entry", subscribe, name = "lfs_subscribeEntry")]
+	fn subscribe_entry(
+		&self,
+		metadata: Self::Metadata,
+		subscriber: Subscriber<EntryChange>,
+		id: Key,
+	);
+
+	/// Stop streaming the changes of an entry
+	#[pubsub(subscription = "lfs_entry", unsubscribe, name = "lfs_unsubscribeEntry")]
+	fn unsubscribe_entry(
+		&self,
+		metadata: Option<Self::Metadata>,
+		id: SubscriptionId,
+	) -> Result<bool>;
+
+	/// Start uploading `size` bytes in chunks, optionally of the `expected` id.
+	/// Returns the id of the session.
+	#[rpc(name = "lfs_beginUpload")]
+	fn begin_upload(&self, size: u64, expected: Option<Key>) -> Result<String>;
+
+	/// Add the `data` at `offset` to the upload `session`, returning the bytes received so far.
+	/// Chunks overlapping the data already received are accepted, to resume after a disconnect.
+	#[rpc(name = "lfs_uploadChunk")]
+	fn upload_chunk(&self, session: String, offset: u64, data: Bytes) -> Result<u64>;
+
+	/// Store the data of the completed upload `session`, returning its id
+	#[rpc(name = "lfs_finishUpload")]
+	fn finish_upload(&self, session: String) -> Result<Key>;
+
+	/// Drop the upload `session` and its data, `false` if there was no such session
+	#[rpc(name = "lfs_abortUpload")]
+	fn abort_upload(&self, session: String) -> Result<bool>;
 }
 
 /// An implementation of System-specific RPC methods.
-pub struct LfsRpc<C> {
+pub struct LfsRpc<C, P = ()> {
 	cache: C,
+	entries: P,
+	subscriptions: Arc<EntrySubscriptions>,
+	/// `0` for no limit
+	max_upload_size: u64,
+	uploads: Option<Bucket>,
+	sessions: Mutex<HashMap<String, UploadSession>>,
+	session_timeout: Duration,
+	max_sessions: usize,
 }
 
 impl<C> LfsRpc<C> {
 	/// Create new `LFS` interface given the cache.
 	pub fn new(cache: C) -> Self {
-		LfsRpc { cache }
+		LfsRpc {
+			cache,
+			entries: (),
+			subscriptions: Default::default(),
+			max_upload_size: 0,
+			uploads: None,
+			sessions: Mutex::new(HashMap::new()),
+			session_timeout: DEFAULT_SESSION_TIMEOUT,
+			max_sessions: DEFAULT_MAX_SESSIONS,
+		}
 	}
 }
 
-impl<C> LfsApi<LfsId> for LfsRpc<C>
+impl<C, P> LfsRpc<C, P> {
+	/// Look up the on chain state of entries for `lfs_stat` with `entries`
+	pub fn with_entries<E>(self, entries: E) -> LfsRpc<C, E> {
+		LfsRpc {
+			cache: self.cache,
+			entries,
+			subscriptions: self.subscriptions,
+			max_upload_size: self.max_upload_size,
+			uploads: self.uploads,
+			sessions: self.sessions,
+			session_timeout: self.session_timeout,
+			max_sessions: self.max_sessions,
+		}
+	}
+
+	/// Reject uploads of more than `max_upload_size` bytes, `0` for no limit
+	pub fn with_max_upload_size(self, max_upload_size: u64) -> Self {
+		LfsRpc {
+			max_upload_size,
+			..self
+		}
+	}
+
+	/// Accept `rate` uploads per second on average and up to `burst` at once,
+	/// a `rate` of `0` for no limit
+	pub fn with_upload_rate(self, rate: f64, burst: u32) -> Self {
+		LfsRpc {
+			uploads: Bucket::new(rate, burst),
+			..self
+		}
+	}
+
+	/// Keep the `lfs_subscribeEntry` subscriptions in `subscriptions`,
+	/// for a task watching the chain to update them
+	pub fn with_entry_subscriptions(self, subscriptions: Arc<EntrySubscriptions>) -> Self {
+		LfsRpc {
+			subscriptions,
+			..self
+		}
+	}
+
+	/// Drop upload sessions not receiving a chunk for `timeout`
+	pub fn with_session_timeout(self, timeout: Duration) -> Self {
+		LfsRpc {
+			session_timeout: timeout,
+			..self
+		}
+	}
+
+	/// Allow up to `max_sessions` upload sessions at once, `0` for no limit
+	pub fn with_max_sessions(self, max_sessions: usize) -> Self {
+		LfsRpc {
+			max_sessions,
+			..self
+		}
+	}
+
+	fn check_upload(&self, size: u64) -> Result<()> {
+		self.check_size(size)?;
+		self.check_rate(1)
+	}
+
+	fn check_size(&self, size: u64) -> Result<()> {
+		if self.max_upload_size > 0 && size > self.max_upload_size {
+			return Err(error(
+				UPLOAD_TOO_LARGE,
+				format!(
+					"Upload of {} bytes exceeds the maximum of {} bytes",
+					size, self.max_upload_size
+				),
+			));
+		}
+		Ok(())
+	}
+
+	/// Take a token of the upload rate for each of `uploads`
+	fn check_rate(&self, uploads: usize) -> Result<()> {
+		let uploads = uploads.min(u32::max_value() as usize) as u32;
+		match self.uploads {
+			Some(ref bucket) if !bucket.take_many(uploads) => Err(error(
+				RATE_LIMITED,
+				"Too many uploads, please retry later".into(),
+			)),
+			_ => Ok(()),
+		}
+	}
+
+	/// The upload sessions, with the abandoned ones removed
+	fn sessions(&self) -> parking_lot::MutexGuard<HashMap<String, UploadSession>> {
+		let mut sessions = self.sessions.lock();
+		let timeout = self.session_timeout;
+		sessions.retain(|_, s| s.touched.elapsed() < timeout);
+		sessions
+	}
+}
+
+impl<C, P> LfsRpc<C, P>
+where
+	C: Cache<LfsId>,
+{
+	/// Store `data` under `expected`, if that is its id
+	fn store_expecting(&self, expected: LfsId, data: &Vec<u8>) -> Result<LfsId> {
+		check_raw(&expected, data.len() as u64)?;
+		if !expected.verify(data) {
+			return Err(error(
+				HASH_MISMATCH,
+				"Uploaded data doesn't match the expected id".into(),
+			));
+		}
+		self.cache
+			.insert(&expected, data)
+			.map(|_| expected)
+			.map_err(|_| ApiError::invalid_params("Data could not be stored"))
+	}
+}
+
+fn unknown_session(session: &str) -> ApiError {
+	error(
+		UNKNOWN_SESSION,
+		format!("No upload session {}, it may have expired", session),
+	)
+}
+
+/// Raw ids hold the data itself, so only short data may be stored under one
+fn check_raw(id: &LfsId, size: u64) -> Result<()> {
+	match id {
+		LfsId::Raw(_) if size > MAX_RAW_LENGTH as u64 => Err(error(
+			RAW_TOO_LARGE,
+			format!(
+				"Raw ids hold at most {} bytes, got {}, use a hash algorithm instead",
+				MAX_RAW_LENGTH, size
+			),
+		)),
+		_ => Ok(()),
+	}
+}
+
+impl<C, P> LfsApi<LfsId> for LfsRpc<C, P>
 where
 	C: Cache<LfsId> + Sync + Send + Clone + 'static,
+	P: EntryStateProvider<LfsId> + 'static,
 {
+	type Metadata = Metadata;
+
 	fn get(&self, id: LfsId) -> Result<Vec<u8>> {
 		if let LfsId::Raw(data) = id {
 			return Ok(data);
@@ -45,9 +513,202 @@ where
 	}
 
 	fn upload(&self, data: Vec<u8>) -> Result<LfsId> {
+		self.check_upload(data.len() as u64)?;
 		self.cache
 			.clone() // FIXME: why do we have to clone here?
 			.store(&data)
 			.map_err(|_| ApiError::invalid_params("Data could not be stored"))
 	}
+
+	fn get_bytes(&self, id: LfsId) -> Result<Bytes> {
+		self.get(id).map(Into::into)
+	}
+
+	fn upload_bytes(&self, data: Bytes) -> Result<LfsId> {
+		self.upload(data.0)
+	}
+
+	fn upload_as(&self, data: Bytes, algorithm: HashAlgorithm) -> Result<LfsId> {
+		self.check_upload(data.len() as u64)?;
+		let id = LfsId::with_algorithm(algorithm, &data.0);
+		self.store_expecting(id, &data.0)
+	}
+
+	fn upload_expecting(&self, data: Bytes, id: LfsId) -> Result<LfsId> {
+		self.check_upload(data.len() as u64)?;
+		self.store_expecting(id, &data.0)
+	}
+
+	fn exists(&self, ids: Vec<LfsId>) -> Result<Vec<bool>> {
+		Ok(ids
+			.iter()
+			.map(|id| match id {
+				LfsId::Raw(_) => true,
+				_ => self.cache.exists(id).unwrap_or(false),
+			})
+			.collect())
+	}
+
+	fn stat(&self, id: LfsId) -> Result<EntryStat> {
+		let size = match id {
+			LfsId::Raw(ref data) => Some(data.len() as u64),
+			_ => self.cache.size(&id).ok(),
+		};
+		let state = self.entries.entry_state(&id, ResolveMode::Best);
+		let pinned = match state {
+			Some(EntryState::Resolved { ref_count, .. }) => ref_count > 0,
+			_ => false,
+		};
+		Ok(EntryStat {
+			size,
+			pinned,
+			state,
+		})
+	}
+
+	fn pending(&self, offset: u32, limit: u32) -> Result<Vec<LfsId>> {
+		Ok(self.entries.pending(offset, limit.min(MAX_PENDING_PAGE)))
+	}
+
+	fn get_many(&self, ids: Vec<LfsId>) -> Result<Vec<Option<Bytes>>> {
+		Ok(ids
+			.into_iter()
+			.map(|id| self.get(id).ok().map(Into::into))
+			.collect())
+	}
+
+	fn upload_many(&self, data: Vec<Bytes>) -> Result<Vec<LfsId>> {
+		// check all before storing any, so a failing batch can simply be retried.
+		// A batch counts like uploading its items one by one.
+		let size = data
+			.iter()
+			.fold(0u64, |size, d| size.saturating_add(d.len() as u64));
+		self.check_size(size)?;
+		self.check_rate(data.len())?;
+		data.iter()
+			.map(|d| {
+				self.cache
+					.store(&d.0)
+					.map_err(|_| ApiError::invalid_params("Data could not be stored"))
+			})
+			.collect()
+	}
+
+	fn subscribe_entry(
+		&self,
+		metadata: Self::Metadata,
+		subscriber: Subscriber<EntryChange>,
+		id: LfsId,
+	) {
+		match metadata.session() {
+			Some(session) => EntrySubscriptions::add(&self.subscriptions, session, id, subscriber),
+			None => {
+				let _ = subscriber.reject(ApiError::invalid_request());
+			}
+		}
+	}
+
+	fn unsubscribe_entry(
+		&self,
+		_metadata: Option<Self::Metadata>,
+		id: SubscriptionId,
+	) -> Result<bool> {
+		Ok(self.subscriptions.remove(&id))
+	}
+
+	fn begin_upload(&self, size: u64, expected: Option<LfsId>) -> Result<String> {
+		let mut sessions = self.sessions();
+		if self.max_sessions > 0 && sessions.len() >= self.max_sessions {
+			return Err(error(
+				TOO_MANY_SESSIONS,
+				"Too many upload sessions, please retry later".into(),
+			));
+		}
+		self.check_upload(size)?;
+		if let Some(ref expected) = expected {
+			check_raw(expected, size)?;
+		}
+
+		let id = format!("{:032x}", rand::random::<u128>());
+		sessions.insert(
+			id.clone(),
+			UploadSession {
+				size,
+				expected,
+				data: Vec::new(),
+				touched: Instant::now(),
+			},
+		);
+		Ok(id)
+	}
+
+	fn upload_chunk(&self, session: String, offset: u64, data: Bytes) -> Result<u64> {
+		let mut sessions = self.sessions();
+		let upload = sessions
+			.get_mut(&session)
+			.ok_or_else(|| unknown_session(&session))?;
+		let received = upload.data.len() as u64;
+		if offset > received {
+			return Err(ApiError {
+				data: Some(received.into()),
+				..error(
+					INVALID_OFFSET,
+					format!("Chunk at {} leaves a gap after {} bytes", offset, received),
+				)
+			});
+		}
+		let end = offset + data.len() as u64;
+		if end > upload.size {
+			return Err(error(
+				UPLOAD_TOO_LARGE,
+				format!(
+					"Chunk ending at {} exceeds the announced size of {} bytes",
+					end, upload.size
+				),
+			));
+		}
+		// skip what we have already, e.g. when resuming
+		if end > received {
+			upload
+				.data
+				.extend_from_slice(&data[(received - offset) as usize..]);
+		}
+		upload.touched = Instant::now();
+		Ok(upload.data.len() as u64)
+	}
+
+	fn finish_upload(&self, session: String) -> Result<LfsId> {
+		let upload = {
+			let mut sessions = self.sessions();
+			let upload = sessions
+				.get(&session)
+				.ok_or_else(|| unknown_session(&session))?;
+			let received = upload.data.len() as u64;
+			if received < upload.size {
+				// keep the session around, the client may still send the rest
+				return Err(ApiError {
+					data: Some(received.into()),
+					..error(
+						INCOMPLETE_UPLOAD,
+						format!("Received {} of {} bytes", received, upload.size),
+					)
+				});
+			}
+			sessions
+				.remove(&session)
+				.expect("We just looked the session up. qed")
+		};
+
+		match upload.expected {
+			Some(expected) => self.store_expecting(expected, &upload.data),
+			None => self
+				.cache
+				.store(&upload.data)
+				.map_err(|_| ApiError::invalid_params("Data could not be stored")),
+		}
+	}
+
+	fn abort_upload(&self, session: String) -> Result<bool> {
+		Ok(self.sessions().remove(&session).is_some())
+	}
 }
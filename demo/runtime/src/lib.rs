@@ -14,8 +14,8 @@ use sp_api::impl_runtime_apis;
 use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_core::OpaqueMetadata;
 use sp_runtime::traits::{
-	self, BlakeTwo256, Block as BlockT, ConvertInto, IdentifyAccount, SaturatedConversion,
-	StaticLookup, Verify,
+	self, BlakeTwo256, Block as BlockT, ConvertInto, IdentifyAccount, Identity,
+	SaturatedConversion, StaticLookup, Verify,
 };
 use sp_runtime::{
 	create_runtime_str, generic, impl_opaque_keys, transaction_validity::TransactionValidity,
@@ -97,7 +97,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("lfs-demo"),
 	impl_name: create_runtime_str!("lfs-demo"),
 	authoring_version: 1,
-	spec_version: 1,
+	spec_version: 2,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
 };
@@ -264,7 +264,7 @@ impl utility::Trait for Runtime {
 /// Used for the module template in `./template.rs`
 impl pallet_lfs_user_data::Trait for Runtime {
 	type Event = Event;
-	type Callback = Call;
+	type ListenerContext = Identity;
 	type KeyGuard = (
 		pallet_lfs_user_data::guard::DefaultUserKeys,
 		pallet_lfs_user_data::guard::Homepage,
@@ -274,11 +274,22 @@ impl pallet_lfs_user_data::Trait for Runtime {
 type LfsTransactionSubmitter = TransactionSubmitter<LfsAppKeyPublic, Runtime, UncheckedExtrinsic>;
 
 /// Setup
+parameter_types! {
+	pub const LfsByteDeposit: Balance = MILLICENTS;
+	pub const LfsByteFee: Balance = MILLICENTS / 100_000;
+	pub const LfsQueryTimeout: BlockNumber = HOURS;
+}
+
 impl pallet_lfs::Trait for Runtime {
 	type Event = Event;
 	type OcwCall = Call;
-	type Callback = Call;
+	type Listener = UserData;
+	type QueryTimeout = LfsQueryTimeout;
 	type SubmitTransaction = LfsTransactionSubmitter;
+	type Validator = ();
+	type Currency = Balances;
+	type ByteDeposit = LfsByteDeposit;
+	type ByteFee = LfsByteFee;
 }
 
 construct_runtime!(
@@ -455,4 +466,20 @@ impl_runtime_apis! {
 			Grandpa::grandpa_authorities()
 		}
 	}
+
+	impl sp_lfs_runtime_api::LfsApi<Block, AccountId, BlockNumber> for Runtime {
+		fn entry_state(
+			key: sp_lfs_core::LfsReference,
+		) -> Option<sp_lfs_runtime_api::EntryState<BlockNumber>> {
+			Lfs::entry_state(&key)
+		}
+
+		fn authorities() -> Vec<AccountId> {
+			Lfs::authorities()
+		}
+
+		fn pending(offset: u32, limit: u32) -> Vec<sp_lfs_core::LfsReference> {
+			Lfs::pending(offset, limit)
+		}
+	}
 }
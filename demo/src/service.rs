@@ -1,7 +1,7 @@
 //! Service and ServiceFactory implementation. Specialized wrapper over substrate service.
 
 use grandpa::{self, FinalityProofProvider as GrandpaFinalityProofProvider};
-use lfs_demo_runtime::{self, opaque::Block, GenesisConfig, RuntimeApi};
+use lfs_demo_runtime::{self, opaque::Block, AccountId, GenesisConfig, RuntimeApi};
 use sc_client::LongestChain;
 use sc_executor::native_executor_instance;
 pub use sc_executor::NativeExecutor;
@@ -96,27 +96,23 @@ pub fn new_full(
 	let disable_grandpa = config.disable_grandpa;
 	let dev_seed = config.dev_key_seed.clone();
 
-	let lfs = LfsClient::from_config(
-		&load_lfs_config(
-			config
-				.in_chain_config_dir("lfs.toml")
-				.expect("We always have a path")
-				.as_path(),
-		)?,
-		|p| {
-			p.as_path()
-				.to_str()
-				.map(|s| {
-					config
-						.in_chain_config_dir(s)
-						.expect("Chain configuration directory is always defined.")
-				})
-				.ok_or(format!(
-					"Could not convert LFS configuration path '{:?}' into OS string",
-					p
-				))
-		},
-	)?;
+	let lfs_config_path = config
+		.in_chain_config_dir("lfs.toml")
+		.expect("We always have a path");
+	let lfs_config = load_lfs_config(lfs_config_path.as_path())?;
+	let lfs = LfsClient::from_config(&lfs_config, |p| {
+		p.as_path()
+			.to_str()
+			.map(|s| {
+				config
+					.in_chain_config_dir(s)
+					.expect("Chain configuration directory is always defined.")
+			})
+			.ok_or(format!(
+				"Could not convert LFS configuration path '{:?}' into OS string",
+				p
+			))
+	})?;
 
 	// sentry nodes announce themselves as authorities to the network
 	// and should run the same protocols authorities do, but it should
@@ -135,14 +131,22 @@ pub fn new_full(
 			Ok(Arc::new(GrandpaFinalityProofProvider::new(backend, client)) as _)
 		})?
 		.with_execution_extensions_factory(lfs.make_externalities_extension_factory())?
-		.with_rpc_extensions(|_client, _pool, _backend, _, _| {
+		.with_rpc_extensions(|client, _pool, _backend, _, _| {
 			use sc_lfs::rpc::LfsApi;
-			let mut io = jsonrpc_core::IoHandler::default();
-			io.extend_with(LfsApi::to_delegate(lfs.make_rpc()));
+			let entries =
+				sc_lfs::entries::RuntimeEntries::<_, Block, AccountId>::new(client.clone());
+			let mut io = jsonrpc_core::IoHandler::<sc_lfs::rpc::Metadata>::default();
+			io.extend_with(LfsApi::to_delegate(lfs.make_rpc().with_entries(entries)));
 			Ok(io)
 		})?
 		.build()?;
 
+	service.spawn_task(
+		"lfs-entries",
+		sc_lfs::entries::RuntimeEntries::<_, Block, AccountId>::new(service.client())
+			.watch(lfs.entry_subscriptions()),
+	);
+
 	if participates_in_consensus {
 		let proposer = sc_basic_authorship::ProposerFactory {
 			client: service.client(),
@@ -175,17 +179,42 @@ pub fn new_full(
 		service.spawn_essential_task("aura", aura);
 	}
 
-	let user_data_resolver = sc_lfs_http_server::user_data::UserDataResolver::<
-		_,
-		_,
-		_,
-		_,
-		lfs_demo_runtime::Runtime,
-	>::new(service.client());
+	let client = service.client();
+	let rules_cache = lfs.cache().clone();
+	// rebuilt whenever the configuration is reloaded
+	let router_for = move |config: &sc_lfs_http_server::ServerConfig| {
+		let user_data_resolver = sc_lfs_http_server::user_data::UserDataResolver::<
+			_,
+			_,
+			_,
+			_,
+			lfs_demo_runtime::Runtime,
+		>::new(client.clone())
+		.with_mode(config.resolve_mode)
+		.with_unknown_host_fallback(config.unknown_host_fallback)
+		.with_rules(rules_cache.clone());
+
+		sc_lfs_http_server::Router::from_config(&config.routes, |name| match name {
+			sc_lfs_http_server::router::RAW_RESOLVER => Some(Arc::new(()) as _),
+			sc_lfs_http_server::user_data::RESOLVER_NAME => {
+				Some(Arc::new(user_data_resolver.clone()) as _)
+			}
+			_ => None,
+		})
+	};
 
+	let (http_server, http_task) = sc_lfs_http_server::start_server(
+		lfs_config.http.clone(),
+		lfs.cache().clone(),
+		router_for,
+		service.on_exit(),
+	)?;
+	service.spawn_task("http-server", http_task);
 	service.spawn_task(
-		"http-server",
-		sc_lfs_http_server::start_server(lfs.cache().clone(), user_data_resolver),
+		"http-server-reload",
+		http_server.reload_on_change(lfs_config_path, |path| {
+			load_lfs_config(path).map(|config| config.http)
+		}),
 	);
 
 	// if the node isn't actively participating in consensus then it doesn't
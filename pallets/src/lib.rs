@@ -1,19 +1,24 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
-use codec::{Codec, Decode, Encode};
+use codec::{Decode, Encode};
 use sp_runtime::app_crypto::KeyTypeId;
-use sp_runtime::{
-	traits::{Dispatchable, StaticLookup},
-	DispatchError,
-};
+use sp_runtime::traits::{SaturatedConversion, Saturating, StaticLookup};
 use sp_std::prelude::*;
 use support::{
-	decl_event, decl_module, decl_storage, dispatch::DispatchResult, Parameter, StorageValue,
+	decl_event, decl_module, decl_storage,
+	dispatch::DispatchResult,
+	storage::StoragePrefixedMap,
+	traits::{Currency, Get, ReservableCurrency},
+	Parameter, StorageValue,
 };
 use system::offchain::SubmitSignedTransaction;
 use system::{ensure_root, ensure_signed};
 
+use sp_lfs_cache::CacheError;
 use sp_lfs_core::LfsReference;
+use sp_lfs_runtime_api::EntryState;
+
+pub use validation::{ValidationSpec, Validator};
 
 pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"lfs0");
 
@@ -29,6 +34,223 @@ pub mod ed25519 {
 	app_crypto!(ed25519, KEY_TYPE);
 }
 
+pub mod validation {
+	use codec::{Decode, Encode};
+	use sp_lfs_core::LfsReference;
+	use sp_runtime::RuntimeDebug;
+	use sp_std::prelude::*;
+
+	/// How many bytes at the beginning of the data are inspected to detect its MIME type
+	pub const SNIFF_LENGTH: u32 = 16;
+
+	/// What the data of an entry must look like, evaluated by the authorities before
+	/// responding to a query. Also records what the data was found to be: its size,
+	/// its MIME type, if detected, and the checks it passed.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Default, RuntimeDebug)]
+	pub struct ValidationSpec {
+		/// The largest size accepted in bytes
+		pub max_size: Option<u64>,
+		/// The MIME types accepted, e.g. `image/png`, any type if `None`
+		pub mime_types: Option<Vec<Vec<u8>>>,
+		/// Ids of the checks of the runtime's `Validator` the data must pass
+		pub checks: Vec<u32>,
+	}
+
+	impl ValidationSpec {
+		/// Whether data of `size` bytes, having passed `validated`, is known to meet this spec
+		pub fn is_met_by(&self, validated: &Self, size: u64) -> bool {
+			let mime_types = match (&self.mime_types, &validated.mime_types) {
+				(None, _) => true,
+				(Some(_), None) => false,
+				(Some(wanted), Some(passed)) => passed.iter().all(|m| wanted.contains(m)),
+			};
+			self.max_size.map(|max| size <= max).unwrap_or(true)
+				&& mime_types
+				&& self.checks.iter().all(|c| validated.checks.contains(c))
+		}
+	}
+
+	/// The MIME type of `data`, as far as it can be told from its first bytes
+	pub fn sniff_mime(data: &[u8]) -> Option<&'static [u8]> {
+		if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+			Some(b"image/png")
+		} else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+			Some(b"image/jpeg")
+		} else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+			Some(b"image/gif")
+		} else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+			Some(b"image/webp")
+		} else if data.starts_with(b"%PDF-") {
+			Some(b"application/pdf")
+		} else {
+			None
+		}
+	}
+
+	/// Custom checks of the data of entries, run by the authorities in the offchain worker
+	pub trait Validator {
+		/// Whether the data of `key` passes the check `check`,
+		/// reading it via `sp_lfs_cache::lfs_cache_interface`
+		fn validate(_check: u32, _key: &LfsReference) -> bool {
+			false
+		}
+	}
+
+	/// Knows no checks, failing all of them
+	impl Validator for () {}
+
+	impl<A, B> Validator for (A, B)
+	where
+		A: Validator,
+		B: Validator,
+	{
+		fn validate(check: u32, key: &LfsReference) -> bool {
+			A::validate(check, key) || B::validate(check, key)
+		}
+	}
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+
+		fn found(size: u64, mime: Option<&[u8]>, checks: Vec<u32>) -> ValidationSpec {
+			ValidationSpec {
+				max_size: Some(size),
+				mime_types: mime.map(|m| vec![m.to_vec()]),
+				checks,
+			}
+		}
+
+		#[test]
+		fn anything_meets_the_default_spec() {
+			let spec = ValidationSpec::default();
+			assert!(spec.is_met_by(&found(1 << 30, None, vec![]), 1 << 30));
+		}
+
+		#[test]
+		fn size_must_not_exceed_the_maximum() {
+			let spec = ValidationSpec {
+				max_size: Some(100),
+				..Default::default()
+			};
+			assert!(spec.is_met_by(&found(100, None, vec![]), 100));
+			assert!(!spec.is_met_by(&found(101, None, vec![]), 101));
+		}
+
+		#[test]
+		fn mime_type_must_be_detected_and_accepted() {
+			let spec = ValidationSpec {
+				mime_types: Some(vec![b"image/png".to_vec(), b"image/gif".to_vec()]),
+				..Default::default()
+			};
+			assert!(spec.is_met_by(&found(10, Some(b"image/gif"), vec![]), 10));
+			assert!(!spec.is_met_by(&found(10, Some(b"application/pdf"), vec![]), 10));
+			assert!(!spec.is_met_by(&found(10, None, vec![]), 10));
+		}
+
+		#[test]
+		fn all_checks_must_be_passed() {
+			let spec = ValidationSpec {
+				checks: vec![1, 2],
+				..Default::default()
+			};
+			assert!(spec.is_met_by(&found(10, None, vec![2, 3, 1]), 10));
+			assert!(!spec.is_met_by(&found(10, None, vec![1]), 10));
+		}
+
+		#[test]
+		fn sniffs_common_formats() {
+			assert_eq!(
+				sniff_mime(b"\x89PNG\r\n\x1a\n...."),
+				Some(&b"image/png"[..])
+			);
+			assert_eq!(sniff_mime(b"GIF89a..."), Some(&b"image/gif"[..]));
+			assert_eq!(
+				sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "),
+				Some(&b"image/webp"[..])
+			);
+			assert_eq!(sniff_mime(b"RIFF\0\0\0\0WAVE"), None);
+			assert_eq!(sniff_mime(b"<html>"), None);
+			assert_eq!(sniff_mime(b""), None);
+		}
+	}
+}
+
+/// Version of the layout of the storage, see `Module::migrate`
+const STORAGE_VERSION: u32 = 1;
+
+pub type BalanceOf<T> =
+	<<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+
+/// The context queries of the runtime's listener carry
+pub type ContextOf<T> = <<T as Trait>::Listener as LfsListener>::Context;
+
+/// A query waiting for an entry to resolve: what the listener needs to know about it,
+/// the account paying the deposit for the data, if any, and what the data must look like
+pub type Request<T> = (
+	ContextOf<T>,
+	Option<<T as system::Trait>::AccountId>,
+	ValidationSpec,
+);
+
+/// Informed about the entries it queried, implemented by the pallets building on this one
+pub trait LfsListener {
+	/// What the listener needs to know about a query once it is answered
+	type Context: Parameter;
+
+	/// The data of `key`, queried with `context`, was confirmed to exist.
+	/// Returns whether the listener refers to the entry from now on.
+	fn on_resolved(_key: &LfsReference, _context: Self::Context) -> bool {
+		false
+	}
+
+	/// The query for `key` with `context` expired or was rejected, without the data being found
+	fn on_expired(_key: &LfsReference, _context: Self::Context) {}
+
+	/// Nothing refers to the data of `key` anymore, it was dropped
+	fn on_dropped(_key: &LfsReference) {}
+}
+
+/// Listens to nothing, never referring to any entry
+impl LfsListener for () {
+	type Context = ();
+}
+
+/// The context of a query by either of two combined listeners
+#[derive(Encode, Decode, Clone, PartialEq, Eq, sp_runtime::RuntimeDebug)]
+pub enum CombinedContext<A, B> {
+	First(A),
+	Second(B),
+}
+
+/// Combines two listeners, informing the one that queried an entry. Nest the
+/// tuples, e.g. `(A, (B, C))`, for more.
+impl<A, B> LfsListener for (A, B)
+where
+	A: LfsListener,
+	B: LfsListener,
+{
+	type Context = CombinedContext<A::Context, B::Context>;
+
+	fn on_resolved(key: &LfsReference, context: Self::Context) -> bool {
+		match context {
+			CombinedContext::First(context) => A::on_resolved(key, context),
+			CombinedContext::Second(context) => B::on_resolved(key, context),
+		}
+	}
+
+	fn on_expired(key: &LfsReference, context: Self::Context) {
+		match context {
+			CombinedContext::First(context) => A::on_expired(key, context),
+			CombinedContext::Second(context) => B::on_expired(key, context),
+		}
+	}
+
+	fn on_dropped(key: &LfsReference) {
+		A::on_dropped(key);
+		B::on_dropped(key);
+	}
+}
+
 /// The module's configuration trait.
 pub trait Trait: system::Trait {
 	/// The overarching event type.
@@ -37,11 +259,26 @@ pub trait Trait: system::Trait {
 	/// Offchain Worker Call
 	type OcwCall: From<Call<Self>>;
 
-	/// The callback type to call
-	type Callback: Parameter + Dispatchable<Origin = Self::Origin> + Codec + Eq;
+	/// Informed about the entries queried, combine several via tuples
+	type Listener: LfsListener;
+
+	/// Blocks after which a query not responded to expires
+	type QueryTimeout: Get<Self::BlockNumber>;
 
 	/// Let's define the helper we use to create signed transactions with
 	type SubmitTransaction: SubmitSignedTransaction<Self, <Self as Trait>::OcwCall>;
+
+	/// The custom checks `ValidationSpec`s may require
+	type Validator: Validator;
+
+	/// The currency deposits for referenced data are reserved in
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// Reserved from the requester per byte of the data, refunded once dropped
+	type ByteDeposit: Get<BalanceOf<Self>>;
+
+	/// Taken from the deposit per byte of the data and block it was referenced for
+	type ByteFee: Get<BalanceOf<Self>>;
 }
 
 #[derive(Encode, Decode)]
@@ -58,16 +295,13 @@ pub enum LfsOffchainEvent {
 
 #[derive(Encode, Decode)]
 /// The LFS state
-enum LfsEntryState<T: Trait> {
+pub enum LfsEntryState<T: Trait> {
 	/// This entry is pending and hasn't been resolved yet
 	Pending {
 		/// Since when the Block is pending
 		since: T::BlockNumber,
-		/// callbacks to call once resolved
-		listeners: Vec<(
-			<T as Trait>::Callback,
-			Option<<T::Lookup as StaticLookup>::Source>,
-		)>,
+		/// queries to inform once resolved, each with its own spec
+		listeners: Vec<Request<T>>,
 	},
 	Resolved {
 		/// first confirmed to exist
@@ -76,6 +310,10 @@ enum LfsEntryState<T: Trait> {
 		latest: T::BlockNumber,
 		/// how many internally still refer to this entry
 		ref_count: u32,
+		/// size of the data in bytes, as measured by the authority responding
+		size: u64,
+		/// what the data was found to be by the authority responding
+		validated: ValidationSpec,
 	},
 }
 
@@ -87,7 +325,16 @@ decl_storage! {
 		/// The current set of keys that may submit pongs
 		Authorities get(fn authorities) config(authorities): Vec<T::AccountId>;
 		/// The specific LFS entries and states
-		Entries: map hasher(blake2_256) LfsReference => Option<LfsEntryState<T>>;
+		pub Entries get(fn entry): map hasher(blake2_256) LfsReference => Option<LfsEntryState<T>>;
+		/// The entries pending, in the order they were queried, as `Entries` can't be iterated
+		PendingEntries get(fn pending_entries): Vec<LfsReference>;
+		/// The queries of resolved entries waiting for checks their recorded validation lacks,
+		/// and since when the first of them waits
+		Revalidations get(fn revalidations): map hasher(blake2_256) LfsReference => Option<(T::BlockNumber, Vec<Request<T>>)>;
+		/// The deposits reserved for referencing the data of entries: who paid, how much and since when
+		Deposits get(fn deposits): map hasher(blake2_256) LfsReference => Vec<(T::AccountId, BalanceOf<T>, T::BlockNumber)>;
+		/// The layout of the storage, migrated in the first block after an upgrade changing it
+		StorageVersion get(fn storage_version) build(|_| STORAGE_VERSION): u32;
 	}
 }
 
@@ -99,12 +346,15 @@ decl_module! {
 		// this is needed only if you are using events in your module
 		fn deposit_event() = default;
 
-		fn on_initialize(_now: T::BlockNumber) {
+		fn on_initialize(now: T::BlockNumber) {
+			Self::migrate();
 			// clean offchain calls on every block start
 			<Self as Store>::OcwEvents::kill();
+			Self::expire_queries(now);
 		}
-		// Respond to an lfs entry query
-		pub fn respond(origin, key: LfsReference) -> DispatchResult {
+		// Respond to an lfs entry query, with the size of the data measured and what
+		// the data was found to be, resolving the queries whose spec that meets
+		pub fn respond(origin, key: LfsReference, size: u64, validated: ValidationSpec) -> DispatchResult {
 			let author = ensure_signed(origin)?;
 			if !Self::is_authority(&author) {
 				// No known authority, ignore
@@ -116,21 +366,18 @@ decl_module! {
 			if let Some(entry) = Entries::<T>::get(&key) {
 				let replace = match entry {
 					LfsEntryState::Pending { listeners, .. } => {
+						<Self as Store>::PendingEntries::mutate(|v| v.retain(|k| k != &key));
 						// inform the outer OcwEventss about this
 						<Self as Store>::OcwEvents::mutate(|v| v.push(LfsOffchainEvent::Resolved(key.clone())));
-						let mut ref_count = 0u32;
-						// inform the listeners
-						for callback in listeners {
-							if Self::callback(callback) {
-								ref_count += 1;
-							}
-						}
+						let ref_count = Self::resolve_listeners(&key, listeners, &validated, size);
 						// replace with resolved
 						if ref_count > 0 {
 							LfsEntryState::Resolved {
 								ref_count,
 								since: now.clone(),
 								latest: now,
+								size,
+								validated,
 							}
 						} else {
 							// we were able to resolve, but the result didn't lead to any references staying around
@@ -138,11 +385,24 @@ decl_module! {
 							return Ok(());
 						}
 					}
-					LfsEntryState::Resolved { ref_count, since, .. } => {
+					LfsEntryState::Resolved { mut ref_count, since, size, validated: mut recorded, .. } => {
+						if let Some((_, listeners)) = Revalidations::<T>::take(&key) {
+							<Self as Store>::PendingEntries::mutate(|v| v.retain(|k| k != &key));
+							<Self as Store>::OcwEvents::mutate(|v| v.push(LfsOffchainEvent::Resolved(key.clone())));
+							// the data didn't change, only more checks were run on it
+							for check in validated.checks {
+								if !recorded.checks.contains(&check) {
+									recorded.checks.push(check);
+								}
+							}
+							ref_count += Self::resolve_listeners(&key, listeners, &recorded, size);
+						}
 						LfsEntryState::Resolved {
 							since,
 							ref_count,
 							latest: now,
+							size,
+							validated: recorded,
 						}
 					}
 				};
@@ -154,6 +414,29 @@ decl_module! {
 			Ok(())
 		}
 
+		// Reject an lfs entry query, as its data doesn't meet the spec of any of its queries
+		// waiting for a response
+		pub fn reject(origin, key: LfsReference) -> DispatchResult {
+			let author = ensure_signed(origin)?;
+			if !Self::is_authority(&author) {
+				// No known authority, ignore
+				return Ok(())
+			};
+
+			let queried = match Entries::<T>::get(&key) {
+				Some(LfsEntryState::Pending { .. }) => true,
+				Some(LfsEntryState::Resolved { .. }) => Revalidations::<T>::get(&key).is_some(),
+				None => false,
+			};
+			if queried {
+				<Self as Store>::PendingEntries::mutate(|v| v.retain(|k| k != &key));
+				Self::expire(&key);
+				Self::deposit_event(RawEvent::Rejected(key));
+			}
+
+			Ok(())
+		}
+
 		fn offchain_worker(_now: T::BlockNumber) {
 			if T::SubmitTransaction::can_sign() {
 				let _ = Self::offchain();
@@ -195,18 +478,25 @@ decl_event!(
 	{
 		/// Triggered on a pong with the corresponding value
 		Ack(u8, AccountId),
+		/// The data of an entry didn't meet its spec, the query was dropped
+		Rejected(LfsReference),
 	}
 );
 
 /// The inner functions other modules build upon
 impl<T: Trait> Module<T> {
 	/// query for an lfs entry
-	pub fn query(
+	pub fn query(key: LfsReference, context: ContextOf<T>) -> DispatchResult {
+		Self::query_with(key, ValidationSpec::default(), None, context)
+	}
+
+	/// query for an lfs entry whose data meets `spec`, reserving the deposit
+	/// for its data from `payer` once resolved, if given
+	pub fn query_with(
 		key: LfsReference,
-		callback: (
-			<T as Trait>::Callback,
-			Option<<T::Lookup as StaticLookup>::Source>,
-		),
+		spec: ValidationSpec,
+		payer: Option<T::AccountId>,
+		context: ContextOf<T>,
 	) -> DispatchResult {
 		let now = <system::Module<T>>::block_number();
 		let mut issue_query = false;
@@ -215,31 +505,64 @@ impl<T: Trait> Module<T> {
 				issue_query = true;
 				LfsEntryState::Pending {
 					since: now,
-					listeners: vec![callback],
+					listeners: vec![(context, payer, spec)],
 				}
 			}
-			Some(mut entry) => {
-				match entry {
-					LfsEntryState::Pending {
-						ref mut listeners, ..
-					} => {
-						listeners.push(callback);
-					}
-					LfsEntryState::Resolved {
-						ref mut ref_count, ..
-					} => {
-						if Self::callback(callback) {
-							*ref_count += 1;
-						}
+			Some(LfsEntryState::Pending {
+				since,
+				mut listeners,
+			}) => {
+				listeners.push((context, payer, spec));
+				LfsEntryState::Pending { since, listeners }
+			}
+			Some(LfsEntryState::Resolved {
+				since,
+				latest,
+				mut ref_count,
+				size,
+				validated,
+			}) => {
+				if !spec.is_met_by(&validated, size) {
+					// the checks recorded don't tell, have the authorities run the missing ones
+					let request = (context, payer, spec);
+					let mut first = false;
+					Revalidations::<T>::mutate(&key, |r| {
+						let (_, listeners) = r.get_or_insert_with(|| {
+							first = true;
+							(now, Vec::new())
+						});
+						listeners.push(request);
+					});
+					if first {
+						<Self as Store>::PendingEntries::mutate(|v| v.push(key.clone()));
+						<Self as Store>::OcwEvents::mutate(|v| {
+							v.push(LfsOffchainEvent::Query(key))
+						});
 					}
-				};
-				entry
+					return Ok(());
+				}
+				if let Some(ref payer) = payer {
+					Self::reserve_deposit(&key, payer, size)?;
+				}
+				if T::Listener::on_resolved(&key, context) {
+					ref_count += 1;
+				} else if let Some(ref payer) = payer {
+					Self::refund_deposit(&key, payer, size);
+				}
+				LfsEntryState::Resolved {
+					since,
+					latest,
+					ref_count,
+					size,
+					validated,
+				}
 			}
 		};
 
 		Entries::<T>::insert(&key, new_entry);
 
 		if issue_query {
+			<Self as Store>::PendingEntries::mutate(|v| v.push(key.clone()));
 			// Informing the offchain worker
 			<Self as Store>::OcwEvents::mutate(|v| v.push(LfsOffchainEvent::Query(key)));
 		}
@@ -249,14 +572,35 @@ impl<T: Trait> Module<T> {
 
 	/// indicate that you are not using a previously resolved reference anymore
 	pub fn drop(key: LfsReference) -> DispatchResult {
+		Self::drop_for(key, None)
+	}
+
+	/// indicate that you are not using a previously resolved reference anymore,
+	/// refunding the deposit `payer` paid for it, less the fee
+	pub fn drop_for(key: LfsReference, payer: Option<&T::AccountId>) -> DispatchResult {
 		if let Some(mut entry) = Entries::<T>::get(&key) {
 			if let LfsEntryState::Resolved {
-				ref mut ref_count, ..
+				ref mut ref_count,
+				size,
+				..
 			} = entry
 			{
+				if let Some(payer) = payer {
+					Self::refund_deposit(&key, payer, size);
+				}
 				*ref_count -= 1;
 				if *ref_count == 0 {
+					// queries waiting for more checks won't be answered anymore
+					if Revalidations::<T>::get(&key).is_some() {
+						<Self as Store>::PendingEntries::mutate(|v| v.retain(|k| k != &key));
+						Self::expire(&key);
+					}
+					// nobody refers to it anymore, return what is left
+					for (payer, ..) in Deposits::<T>::get(&key) {
+						Self::refund_deposit(&key, &payer, size);
+					}
 					Entries::<T>::remove(&key);
+					T::Listener::on_dropped(&key);
 					// Informing the offchain worker
 					<Self as Store>::OcwEvents::mutate(|v| {
 						v.push(LfsOffchainEvent::Dropped(key.clone()))
@@ -269,32 +613,165 @@ impl<T: Trait> Module<T> {
 		Ok(())
 	}
 
-	// test
-	fn callback(
-		callback: (
-			<T as Trait>::Callback,
-			Option<<T::Lookup as StaticLookup>::Source>,
-		),
-	) -> bool {
-		let (cb, who) = callback;
-		let origin = if let Some(who) = who {
-			if let Ok(sign) = T::Lookup::lookup(who) {
-				system::RawOrigin::Signed(sign).into()
-			} else {
-				sp_runtime::print("Callback not issued, Lookup failed");
-				return false;
-			}
-		} else {
-			system::RawOrigin::Root.into()
+	/// The state of the entry of `key`, `None` if there is none
+	pub fn entry_state(key: &LfsReference) -> Option<EntryState<T::BlockNumber>> {
+		Entries::<T>::get(key).map(|entry| match entry {
+			LfsEntryState::Pending {
+				since, listeners, ..
+			} => EntryState::Pending {
+				since,
+				listeners: listeners.len() as u32,
+			},
+			LfsEntryState::Resolved {
+				since,
+				latest,
+				ref_count,
+				size,
+				..
+			} => EntryState::Resolved {
+				since,
+				latest,
+				ref_count,
+				size,
+			},
+		})
+	}
+
+	/// Up to `limit` of the pending entries, skipping the first `offset`
+	pub fn pending(offset: u32, limit: u32) -> Vec<LfsReference> {
+		Self::pending_entries()
+			.into_iter()
+			.skip(offset as usize)
+			.take(limit as usize)
+			.collect()
+	}
+
+	/// Reserve the deposit for `size` bytes from `payer`, recording it for `key`
+	fn reserve_deposit(key: &LfsReference, payer: &T::AccountId, size: u64) -> DispatchResult {
+		let amount = T::ByteDeposit::get().saturating_mul(size.saturated_into());
+		T::Currency::reserve(payer, amount)?;
+		let now = <system::Module<T>>::block_number();
+		Deposits::<T>::mutate(key, |d| d.push((payer.clone(), amount, now)));
+		Ok(())
+	}
+
+	/// Return a deposit `payer` reserved for `key`, less the fee for the blocks since,
+	/// which is burnt
+	fn refund_deposit(key: &LfsReference, payer: &T::AccountId, size: u64) {
+		let mut deposits = Deposits::<T>::get(key);
+		let index = match deposits.iter().position(|(who, ..)| who == payer) {
+			Some(index) => index,
+			None => return,
 		};
+		let (payer, amount, since) = deposits.remove(index);
+		let now = <system::Module<T>>::block_number();
+		let blocks = now.saturating_sub(since).saturated_into::<u64>();
+		let fee = T::ByteFee::get()
+			.saturating_mul(size.saturated_into())
+			.saturating_mul(blocks.saturated_into())
+			.min(amount);
+		let _ = T::Currency::slash_reserved(&payer, fee);
+		T::Currency::unreserve(&payer, amount.saturating_sub(fee));
+
+		if deposits.is_empty() {
+			Deposits::<T>::remove(key);
+		} else {
+			Deposits::<T>::insert(key, deposits);
+		}
+	}
+
+	/// Bring the storage up to `STORAGE_VERSION`, once after an upgrade
+	fn migrate() {
+		if <Self as Store>::StorageVersion::get() >= STORAGE_VERSION {
+			return;
+		}
+		// Entries of before version 1 hold dispatchable callbacks and lack the size of their
+		// data, they can't be decoded anymore. Drop them, they are queried again once referred to.
+		<Entries<T> as StoragePrefixedMap<LfsEntryState<T>>>::remove_all();
+		<Self as Store>::StorageVersion::put(STORAGE_VERSION);
+	}
 
-		match cb.dispatch(origin) {
-			Ok(_) => true,
-			Err(e) => {
-				let e: DispatchError = e.into();
-				sp_runtime::print(e);
-				false
+	/// Expire the queries of `key` waiting for a response, informing their listeners.
+	/// A pending entry is dropped, a resolved one stays for the queries it met.
+	fn expire(key: &LfsReference) {
+		match Entries::<T>::get(key) {
+			Some(LfsEntryState::Pending { listeners, .. }) => {
+				Entries::<T>::remove(key);
+				for (context, ..) in listeners {
+					T::Listener::on_expired(key, context);
+				}
+				// Informing the offchain worker
+				<Self as Store>::OcwEvents::mutate(|v| {
+					v.push(LfsOffchainEvent::Dropped(key.clone()))
+				});
 			}
+			Some(LfsEntryState::Resolved { .. }) => {
+				if let Some((_, listeners)) = Revalidations::<T>::take(key) {
+					for (context, ..) in listeners {
+						T::Listener::on_expired(key, context);
+					}
+				}
+			}
+			None => {}
+		}
+	}
+
+	/// Resolve the queries of `listeners` whose spec is met by the data of `key`, of `size`
+	/// bytes and having passed `validated`, and expire the others.
+	/// Returns how many listeners keep referring to the entry.
+	fn resolve_listeners(
+		key: &LfsReference,
+		listeners: Vec<Request<T>>,
+		validated: &ValidationSpec,
+		size: u64,
+	) -> u32 {
+		let mut ref_count = 0u32;
+		for (context, payer, spec) in listeners {
+			if !spec.is_met_by(validated, size) {
+				T::Listener::on_expired(key, context);
+				continue;
+			}
+			if let Some(ref payer) = payer {
+				if Self::reserve_deposit(key, payer, size).is_err() {
+					sp_runtime::print("Listener not informed, deposit can't be reserved");
+					T::Listener::on_expired(key, context);
+					continue;
+				}
+			}
+			if T::Listener::on_resolved(key, context) {
+				ref_count += 1;
+			} else if let Some(ref payer) = payer {
+				Self::refund_deposit(key, payer, size);
+			}
+		}
+		ref_count
+	}
+
+	/// Expire all entries pending for longer than the `QueryTimeout`
+	fn expire_queries(now: T::BlockNumber) {
+		let timeout = T::QueryTimeout::get();
+		let mut pending = Self::pending_entries();
+		// entries are pending in the order they were queried, the expired ones come first
+		let expired = pending
+			.iter()
+			.take_while(|key| {
+				let since = match Entries::<T>::get(key) {
+					Some(LfsEntryState::Pending { since, .. }) => Some(since),
+					Some(LfsEntryState::Resolved { .. }) => {
+						Revalidations::<T>::get(key).map(|(since, _)| since)
+					}
+					None => None,
+				};
+				since.map_or(true, |since| since.saturating_add(timeout) <= now)
+			})
+			.count();
+		if expired == 0 {
+			return;
+		}
+		let expired = pending.drain(..expired).collect::<Vec<_>>();
+		<Self as Store>::PendingEntries::put(pending);
+		for key in expired {
+			Self::expire(&key);
 		}
 	}
 }
@@ -307,12 +784,38 @@ impl<T: Trait> Module<T> {
 			match e {
 				LfsOffchainEvent::Query(key) => {
 					sp_io::misc::print_utf8(b"Received query, sending response");
-					match sp_lfs_cache::lfs_cache_interface::exists(&key) {
-						Ok(true) => {
+					let listeners = match Entries::<T>::get(&key) {
+						Some(LfsEntryState::Pending { listeners, .. }) => listeners,
+						Some(LfsEntryState::Resolved { .. }) => match Self::revalidations(&key) {
+							Some((_, listeners)) => listeners,
+							None => continue,
+						},
+						None => continue,
+					};
+					let specs = listeners
+						.into_iter()
+						.map(|(_, _, spec)| spec)
+						.collect::<Vec<_>>();
+					match Self::validate(&key, &specs) {
+						Ok((size, validated))
+							if specs.iter().any(|s| s.is_met_by(&validated, size)) =>
+						{
 							sp_io::misc::print_utf8(b"Found in local cache");
-							let call = Call::respond(key);
+							let call = Call::respond(key, size, validated);
+							let _ = T::SubmitTransaction::submit_signed(call);
+						}
+						Ok(_) => {
+							sp_io::misc::print_utf8(b"Found, but not meeting the spec");
+							let call = Call::reject(key);
 							let _ = T::SubmitTransaction::submit_signed(call);
 						}
+						Err(CacheError::Unavailable) => {
+							// no point in checking the other queries
+							sp_io::misc::print_utf8(
+								b"LFS cache unavailable, not responding to queries",
+							);
+							return;
+						}
 						_ => {
 							sp_io::misc::print_utf8(b"Not found");
 						}
@@ -323,6 +826,31 @@ impl<T: Trait> Module<T> {
 		}
 	}
 
+	/// The size of the data of `key` and what it was found to be, running the checks
+	/// required by any of `specs`
+	fn validate(
+		key: &LfsReference,
+		specs: &[ValidationSpec],
+	) -> Result<(u64, ValidationSpec), CacheError> {
+		let size = sp_lfs_cache::lfs_cache_interface::size(key)?;
+		let head = sp_lfs_cache::lfs_cache_interface::read_range(key, 0, validation::SNIFF_LENGTH)?;
+		let mime_types = validation::sniff_mime(&head).map(|mime| vec![mime.to_vec()]);
+		let mut checks: Vec<u32> = Vec::new();
+		for check in specs.iter().flat_map(|s| s.checks.iter()) {
+			if !checks.contains(check) && T::Validator::validate(*check, key) {
+				checks.push(*check);
+			}
+		}
+		Ok((
+			size,
+			ValidationSpec {
+				max_size: Some(size),
+				mime_types,
+				checks,
+			},
+		))
+	}
+
 	/// Helper that confirms whether the given `AccountId` can sign `pong` transactions
 	fn is_authority(who: &T::AccountId) -> bool {
 		Self::authorities().into_iter().find(|i| i == who).is_some()
@@ -2,14 +2,51 @@
 /// A runtime module to manage user data per accounts, using `LfsReference`s
 ///
 use frame_support::{decl_event, decl_module, decl_storage, dispatch};
-use pallet_lfs::{Module as LfsModule, Trait as LfsTrait};
+use pallet_lfs::{ContextOf, LfsListener, Module as LfsModule, Trait as LfsTrait};
 use sp_lfs_core::LfsReference;
+use sp_runtime::traits::Convert;
 use sp_std::prelude::*;
 use system::{ensure_root, ensure_signed};
 
 /// Local alias for a named storage entry
 pub type EntryKey = Vec<u8>;
 
+/// A lowercase domain name, e.g. `example.com`
+pub type Domain = Vec<u8>;
+
+/// The account, the key and the nonce of an update waiting for its entry
+pub type UpdateContext<T> = (<T as system::Trait>::AccountId, EntryKey, u32);
+
+/// Longest domain name accepted, as per RFC 1035
+pub const MAX_DOMAIN_LENGTH: usize = 253;
+
+/// Top level domains that never resolve publicly, as per RFC 6761 and RFC 6762
+pub const RESERVED_TLDS: &[&[u8]] = &[b"localhost", b"local", b"invalid", b"test", b"example"];
+
+/// Is this a plausible, lowercase, public domain name?
+///
+/// Single labels, like `localhost`, reserved top level domains and IP literals
+/// are refused, as a node can't tell them apart from its own local names.
+pub fn is_valid_domain(domain: &[u8]) -> bool {
+	let tld = match domain.rsplit(|c| *c == b'.').next() {
+		Some(tld) => tld,
+		None => return false,
+	};
+	domain.len() <= MAX_DOMAIN_LENGTH
+		&& domain.contains(&b'.')
+		// all-digit top level domains don't exist, they are IPv4 addresses
+		&& !tld.iter().all(|c| c.is_ascii_digit())
+		&& !RESERVED_TLDS.contains(&tld)
+		&& domain.split(|c| *c == b'.').all(|label| {
+			!label.is_empty()
+				&& !label.starts_with(b"-")
+				&& !label.ends_with(b"-")
+				&& label
+					.iter()
+					.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == b'-')
+		})
+}
+
 pub mod guard {
 
 	/// Type which regulates which keys are accepted
@@ -55,8 +92,10 @@ pub mod guard {
 	pub struct Homepage;
 	impl KeyGuardian for Homepage {
 		fn is_allowed(key: &[u8]) -> bool {
-			if key == b"" {
-				return true;
+			match key {
+				// index, catch-all, not-found page and the rules of the site
+				b"" | b".*" | b"_404" | b"_redirects" => return true,
+				_ => {}
 			}
 			if key.starts_with(b"/") {
 				return false;
@@ -97,8 +136,9 @@ use crate::guard::KeyGuardian;
 pub trait Trait: system::Trait + LfsTrait {
 	/// The overarching event type.
 	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
-	/// Generating callback
-	type Callback: From<Call<Self>> + Into<<Self as LfsTrait>::Callback>;
+	/// Turns the context of an update into the one of the runtime's LFS `Listener`,
+	/// e.g. `Identity` if this is the only listener, or into the variant of a `CombinedContext`
+	type ListenerContext: Convert<UpdateContext<Self>, ContextOf<Self>>;
 	/// The type that regulates, which keys are accepted
 	type KeyGuard: KeyGuardian;
 }
@@ -109,6 +149,15 @@ decl_storage! {
 		// We store the LfsId as the Avatar for any AccountId
 		pub UserData get(fn user_data): double_map hasher(twox_128) T::AccountId, hasher(blake2_256) EntryKey => Option<LfsReference>;
 		UserDataChangeNonce get(fn nonce): double_map hasher(twox_128) T::AccountId, hasher(blake2_256) EntryKey => Option<u32>;
+		// The block the entry was last changed in
+		pub UserDataChangedAt get(fn changed_at): double_map hasher(twox_128) T::AccountId, hasher(blake2_256) EntryKey => Option<T::BlockNumber>;
+		// The reference an entry is requested to be updated to, until it is confirmed
+		pub PendingUserData get(fn pending): double_map hasher(twox_128) T::AccountId, hasher(blake2_256) EntryKey => Option<LfsReference>;
+		// All entry keys set per account, as the keys of `UserData` can't be iterated.
+		// Entries can't be removed, so neither are their keys.
+		pub UserDataKeys get(fn keys): map hasher(blake2_256) T::AccountId => Vec<EntryKey>;
+		// The account whose entries are served under a domain
+		pub Domains get(fn domain_owner): map hasher(blake2_256) Domain => Option<T::AccountId>;
 	}
 }
 
@@ -135,29 +184,37 @@ decl_module! {
 			Self::request_to_update(T::AccountId::default(), key, lfs_entry)
 		}
 
-		// callback called once the LFS is confirmedLfsReference
-		fn data_changed(
-			origin,
-			who: T::AccountId,
-			key: EntryKey,
-			nonce: u32,
-			lfs_entry: LfsReference,
-		) -> dispatch::DispatchResult {
+		// add keys set before `UserDataKeys` was introduced to the keys of `who`, as
+		// the keys of `UserData` can't be iterated to find them on chain
+		pub fn root_index_keys(origin, who: T::AccountId, keys: Vec<EntryKey>) -> dispatch::DispatchResult {
 			let _ = ensure_root(origin)?;
-
-			if Some(nonce) == Self::nonce(&who, &key) {
-				if let Some(old_lfs_entry) = UserData::<T>::get(&who, &key) {
-					// There was an entry stored, inform LFS to drop the lfs_entryerence (count)
-					let _ = LfsModule::<T>::drop(old_lfs_entry);
+			UserDataKeys::<T>::mutate(&who, |known| {
+				for key in keys {
+					if UserData::<T>::get(&who, &key).is_some() && !known.contains(&key) {
+						known.push(key);
+					}
 				}
-				// then overwrite the entry with the new value
-				UserData::<T>::insert(&who, &key, lfs_entry);
-				// and inform the public, that the users avatar changed
-				Self::deposit_event(RawEvent::UserDataChanged(who, key))
-			} else {
-				// not the correct one, drop the entry from our list as we won't be using it
-				let _ = LfsModule::<T>::drop(lfs_entry);
+			});
+			Ok(())
+		}
+
+		// give up a domain owned by the sender
+		pub fn release_domain(origin, domain: Domain) -> dispatch::DispatchResult {
+			let who = ensure_signed(origin)?;
+			if Self::domain_owner(&domain) != Some(who) {
+				return Err("Domain not owned".into());
+			}
+			Self::change_domain(domain, None);
+			Ok(())
+		}
+
+		// set or clear the owner of any domain, once its ownership was verified off chain
+		pub fn root_set_domain(origin, domain: Domain, owner: Option<T::AccountId>) -> dispatch::DispatchResult {
+			let _ = ensure_root(origin)?;
+			if owner.is_some() && !is_valid_domain(&domain) {
+				return Err("Invalid domain".into());
 			}
+			Self::change_domain(domain, owner);
 			Ok(())
 		}
 	}
@@ -171,16 +228,69 @@ impl<T: Trait> Module<T> {
 		lfs_entry: LfsReference,
 	) -> dispatch::DispatchResult {
 		let nonce = Self::nonce(&who, &key).unwrap_or(0) + 1;
-		let call: <T as Trait>::Callback =
-			Call::data_changed(who.clone(), key.clone(), nonce, lfs_entry.clone()).into();
+		let context = T::ListenerContext::convert((who.clone(), key.clone(), nonce));
 
 		// store first
 		UserDataChangeNonce::<T>::insert(&who, &key, nonce);
+		PendingUserData::<T>::insert(&who, &key, lfs_entry.clone());
+		// the root account's entries are set by root, which pays no deposit
+		let payer = if who == T::AccountId::default() {
+			None
+		} else {
+			Some(who)
+		};
 		// this maybe fire directly, if the lfs_entry is already known!
-		LfsModule::<T>::query(lfs_entry, (call.into(), None))?;
+		LfsModule::<T>::query_with(lfs_entry, Default::default(), payer, context)?;
 
 		Ok(())
 	}
+
+	fn change_domain(domain: Domain, owner: Option<T::AccountId>) {
+		match owner {
+			Some(ref who) => Domains::<T>::insert(&domain, who),
+			None => Domains::<T>::remove(&domain),
+		}
+		Self::deposit_event(RawEvent::DomainChanged(domain, owner))
+	}
+}
+
+impl<T: Trait> LfsListener for Module<T> {
+	type Context = UpdateContext<T>;
+
+	// the entry requested is confirmed
+	fn on_resolved(lfs_entry: &LfsReference, context: Self::Context) -> bool {
+		let (who, key, nonce) = context;
+		if Some(nonce) != Self::nonce(&who, &key) {
+			// not the latest update requested, we won't be using it
+			return false;
+		}
+		// the latest requested update is confirmed, nothing pending anymore
+		PendingUserData::<T>::remove(&who, &key);
+		match UserData::<T>::get(&who, &key) {
+			// nothing changes, we keep referring to the entry through the existing reference
+			Some(ref old_lfs_entry) if old_lfs_entry == lfs_entry => return false,
+			// There was an entry stored, inform LFS to drop the reference (count)
+			Some(old_lfs_entry) => {
+				let _ = LfsModule::<T>::drop_for(old_lfs_entry, Some(&who));
+			}
+			// a new entry, keep track of it
+			None => UserDataKeys::<T>::mutate(&who, |keys| keys.push(key.clone())),
+		}
+		// then overwrite the entry with the new value
+		UserData::<T>::insert(&who, &key, lfs_entry);
+		UserDataChangedAt::<T>::insert(&who, &key, <system::Module<T>>::block_number());
+		// and inform the public, that the users data changed
+		Self::deposit_event(RawEvent::UserDataChanged(who, key));
+		true
+	}
+
+	// the entry requested was never confirmed, forget about the update
+	fn on_expired(_lfs_entry: &LfsReference, context: Self::Context) {
+		let (who, key, nonce) = context;
+		if Some(nonce) == Self::nonce(&who, &key) {
+			PendingUserData::<T>::remove(&who, &key);
+		}
+	}
 }
 
 decl_event!(
@@ -189,5 +299,38 @@ decl_event!(
 		AccountId = <T as system::Trait>::AccountId,
 	{
 		UserDataChanged(AccountId, EntryKey),
+		DomainChanged(Domain, Option<AccountId>),
 	}
 );
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn accepts_public_domains() {
+		assert!(is_valid_domain(b"example.com"));
+		assert!(is_valid_domain(b"my-site.example.org"));
+		assert!(is_valid_domain(b"1password.com"));
+	}
+
+	#[test]
+	fn refuses_malformed_domains() {
+		assert!(!is_valid_domain(b""));
+		assert!(!is_valid_domain(b"Example.com"));
+		assert!(!is_valid_domain(b"example..com"));
+		assert!(!is_valid_domain(b"-example.com"));
+		assert!(!is_valid_domain(b"example.com:8080"));
+		assert!(!is_valid_domain(&[b'a'; MAX_DOMAIN_LENGTH + 1]));
+	}
+
+	#[test]
+	fn refuses_local_names_and_ip_literals() {
+		assert!(!is_valid_domain(b"localhost"));
+		assert!(!is_valid_domain(b"intranet"));
+		assert!(!is_valid_domain(b"app.localhost"));
+		assert!(!is_valid_domain(b"printer.local"));
+		assert!(!is_valid_domain(b"127.0.0.1"));
+		assert!(!is_valid_domain(b"[::1]"));
+	}
+}
@@ -1,4 +1,5 @@
 #![cfg_attr(not(feature = "std"), no_std)]
+use codec::{Decode, Encode};
 use sp_lfs_core::{LfsId, LfsReference};
 use sp_runtime_interface::runtime_interface;
 use sp_std::prelude::*;
@@ -9,12 +10,40 @@ use sp_externalities::{decl_extension, ExternalitiesExt};
 #[cfg(feature = "std")]
 pub mod shared;
 
+/// Most bytes `lfs_cache_interface::read_range` returns at once
+pub const MAX_READ_LENGTH: u32 = 1024 * 1024;
+
+/// Why a host function of `lfs_cache_interface` failed
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheError {
+	/// The node offers no cache in this context, e.g. outside of offchain workers
+	/// or on nodes without the `LfsCacheExt` extension registered
+	Unavailable,
+	/// The cache couldn't provide or store the data, e.g. as it isn't known
+	Failed,
+}
+
+/// The part of `data` of up to `len` bytes at `offset`, empty if it starts past the end
+fn range_of(data: &[u8], offset: u64, len: u64) -> Vec<u8> {
+	let start = offset.min(data.len() as u64) as usize;
+	let end = offset.saturating_add(len).min(data.len() as u64) as usize;
+	data[start..end].to_vec()
+}
+
 /// Node-side caching interface
 pub trait Cache<Key: LfsId>: Send + Sync {
 	/// this cache knows of `key`
 	fn exists(&self, key: &Key) -> Result<bool, ()>;
 	/// Fetch the data for `key`
 	fn get(&self, key: &Key) -> Result<Vec<u8>, ()>;
+	/// The size of the data for `key` in bytes
+	fn size(&self, key: &Key) -> Result<u64, ()> {
+		self.get(key).map(|d| d.len() as u64)
+	}
+	/// Fetch up to `len` bytes of the data for `key`, starting at `offset`
+	fn read_range(&self, key: &Key, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		self.get(key).map(|d| range_of(&d, offset, len))
+	}
 	// insert the data at `key`
 	fn insert(&self, key: &Key, data: &Vec<u8>) -> Result<(), ()>;
 	/// store data, receive the resulting key
@@ -57,6 +86,16 @@ where
 		})
 	}
 
+	fn size(&self, key: &Key) -> Result<u64, ()> {
+		self.0.size(key).or_else(|_| self.1.size(key))
+	}
+
+	fn read_range(&self, key: &Key, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		self.0
+			.read_range(key, offset, len)
+			.or_else(|_| self.1.read_range(key, offset, len))
+	}
+
 	fn insert(&self, key: &Key, data: &Vec<u8>) -> Result<(), ()> {
 		let _ = self.0.insert(key, data);
 		self.1.insert(key, data)
@@ -72,8 +111,18 @@ pub trait RuntimeCacheInterface: Send + Sync {
 	fn exists(&self, key: &LfsReference) -> Result<bool, ()>;
 	/// Fetch the data for `key`
 	fn get(&self, key: &LfsReference) -> Result<Vec<u8>, ()>;
+	/// The size of the data for `key` in bytes
+	fn size(&self, key: &LfsReference) -> Result<u64, ()> {
+		self.get(key).map(|d| d.len() as u64)
+	}
+	/// Fetch up to `len` bytes of the data for `key`, starting at `offset`
+	fn read_range(&self, key: &LfsReference, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		self.get(key).map(|d| range_of(&d, offset, len))
+	}
 	// insert the data at `key`
 	fn insert(&self, key: &LfsReference, data: &Vec<u8>) -> Result<(), ()>;
+	/// store data, receive the resulting key
+	fn store(&self, data: &Vec<u8>) -> Result<LfsReference, ()>;
 	// mark the following key to be okay to drop
 	fn drop(&self, key: &LfsReference) -> Result<(), ()>;
 }
@@ -105,11 +154,25 @@ where
 		self.0.get(&k)
 	}
 
+	fn size(&self, key: &LfsReference) -> Result<u64, ()> {
+		let k = Key::try_from(key.to_vec()).map_err(|_| ())?;
+		self.0.size(&k)
+	}
+
+	fn read_range(&self, key: &LfsReference, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		let k = Key::try_from(key.to_vec()).map_err(|_| ())?;
+		self.0.read_range(&k, offset, len)
+	}
+
 	fn insert(&self, key: &LfsReference, data: &Vec<u8>) -> Result<(), ()> {
 		let k = Key::try_from(key.to_vec()).map_err(|_| ())?;
 		self.0.insert(&k, data)
 	}
 
+	fn store(&self, data: &Vec<u8>) -> Result<LfsReference, ()> {
+		self.0.store(data).map(Into::into)
+	}
+
 	fn drop(&self, key: &LfsReference) -> Result<(), ()> {
 		let k = Key::try_from(key.to_vec()).map_err(|_| ())?;
 		self.0.drop(&k)
@@ -131,16 +194,49 @@ impl LfsCacheExt {
 #[runtime_interface]
 pub trait LfsCacheInterface {
 	/// Fetch the data for `key`
-	fn get(&mut self, key: &LfsReference) -> Result<Vec<u8>, ()> {
+	fn get(&mut self, key: &LfsReference) -> Result<Vec<u8>, CacheError> {
 		self.extension::<LfsCacheExt>()
-			.expect("LFSCacheExtension must be present")
+			.ok_or(CacheError::Unavailable)?
 			.0
 			.get(key)
+			.map_err(|_| CacheError::Failed)
 	}
-	fn exists(&mut self, key: &LfsReference) -> Result<bool, ()> {
+	fn exists(&mut self, key: &LfsReference) -> Result<bool, CacheError> {
 		self.extension::<LfsCacheExt>()
-			.expect("LFSCacheExtension must be present")
+			.ok_or(CacheError::Unavailable)?
 			.0
 			.exists(key)
+			.map_err(|_| CacheError::Failed)
+	}
+	/// Store `data`, e.g. derived from other entries, under the key the node
+	/// computes for it, so entries can't be stored with data they don't refer to
+	fn store(&mut self, data: &Vec<u8>) -> Result<LfsReference, CacheError> {
+		self.extension::<LfsCacheExt>()
+			.ok_or(CacheError::Unavailable)?
+			.0
+			.store(data)
+			.map_err(|_| CacheError::Failed)
+	}
+	/// The size of the data for `key` in bytes
+	fn size(&mut self, key: &LfsReference) -> Result<u64, CacheError> {
+		self.extension::<LfsCacheExt>()
+			.ok_or(CacheError::Unavailable)?
+			.0
+			.size(key)
+			.map_err(|_| CacheError::Failed)
+	}
+	/// Fetch up to `len` bytes of the data for `key` at `offset`,
+	/// but no more than `MAX_READ_LENGTH` at once
+	fn read_range(
+		&mut self,
+		key: &LfsReference,
+		offset: u64,
+		len: u32,
+	) -> Result<Vec<u8>, CacheError> {
+		self.extension::<LfsCacheExt>()
+			.ok_or(CacheError::Unavailable)?
+			.0
+			.read_range(key, offset, u64::from(len.min(MAX_READ_LENGTH)))
+			.map_err(|_| CacheError::Failed)
 	}
 }
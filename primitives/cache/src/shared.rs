@@ -28,6 +28,12 @@ where
 	fn get(&self, key: &Key) -> Result<Vec<u8>, ()> {
 		self.0.get(key)
 	}
+	fn size(&self, key: &Key) -> Result<u64, ()> {
+		self.0.size(key)
+	}
+	fn read_range(&self, key: &Key, offset: u64, len: u64) -> Result<Vec<u8>, ()> {
+		self.0.read_range(key, offset, len)
+	}
 	fn insert(&self, key: &Key, data: &Vec<u8>) -> Result<(), ()> {
 		self.0.insert(key, data)
 	}
@@ -0,0 +1,47 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+//! Runtime API to query the state of the entries of `pallet_lfs`
+
+use codec::{Codec, Decode, Encode};
+use sp_lfs_core::LfsReference;
+use sp_runtime::RuntimeDebug;
+use sp_std::prelude::*;
+
+/// The state of an LFS entry on chain
+#[derive(Encode, Decode, Clone, PartialEq, Eq, RuntimeDebug)]
+pub enum EntryState<BlockNumber> {
+	/// This entry has been queried but not been resolved yet
+	Pending {
+		/// The block it was first queried at
+		since: BlockNumber,
+		/// How many wait for it to be resolved
+		listeners: u32,
+	},
+	/// This entry has been confirmed to exist
+	Resolved {
+		/// The block it was first confirmed at
+		since: BlockNumber,
+		/// The block it was last confirmed at
+		latest: BlockNumber,
+		/// How many still refer to this entry
+		ref_count: u32,
+		/// The size of the data in bytes
+		size: u64,
+	},
+}
+
+sp_api::decl_runtime_apis! {
+	/// Query the LFS entries known to the chain
+	pub trait LfsApi<AccountId, BlockNumber> where
+		AccountId: Codec,
+		BlockNumber: Codec,
+	{
+		/// The state of the entry of `key`, `None` if there is none
+		fn entry_state(key: LfsReference) -> Option<EntryState<BlockNumber>>;
+		/// The accounts allowed to respond to queries
+		fn authorities() -> Vec<AccountId>;
+		/// Up to `limit` of the pending entries, skipping the first `offset`,
+		/// in the order they were queried
+		fn pending(offset: u32, limit: u32) -> Vec<LfsReference>;
+	}
+}